@@ -0,0 +1,85 @@
+use markdown_compiler::{compile_markdown, parse_markdown_blocks};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Allocator that keeps track of the bytes in use and of the most that were
+/// in use at once
+struct Counter;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counter {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(in_use, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counter = Counter;
+
+/// A post with a bit of everything, repeated to make the inputs
+const POST: &str = "# Release notes
+
+Some *emphasis*, **strong** text, `code` and a [link][docs] to the docs.
+
+- First item
+- Second item with a [link](https://example.com \"title\")
+  1. Nested
+  2. List
+
+> A quote
+> with two lines
+
+| Name | Time |
+|:-----|-----:|
+| a    |   1s |
+
+```rust
+fn main() {
+    println!(\"Hello\");
+}
+```
+
+---
+
+[docs]: https://example.com/docs
+";
+
+/// Runs `f` with the `source`, giving the time it took and the most extra
+/// bytes that were in use at once
+fn measure(source: &str, f: impl Fn(&str)) -> (Duration, usize) {
+    let base = IN_USE.load(Ordering::Relaxed);
+    PEAK.store(base, Ordering::Relaxed);
+    let start = Instant::now();
+    f(source);
+    (start.elapsed(), PEAK.load(Ordering::Relaxed) - base)
+}
+
+/// Usage `cargo r --release --example bench`, the time per MB should be the
+/// same for every size and the memory of the streaming parser shouldn't grow
+fn main() {
+    println!("{:>6} {:>22} {:>22}", "input", "parse_markdown_blocks", "compile_markdown");
+    for mb in [1, 2, 4, 8, 16] {
+        let source = POST.repeat(mb * 1024 * 1024 / POST.len());
+        let (stream_time, stream_peak) = measure(&source, |s| {
+            parse_markdown_blocks(s).for_each(drop);
+        });
+        let (compile_time, compile_peak) = measure(&source, |s| {
+            compile_markdown(s);
+        });
+
+        let per_mb = |time: Duration| time.as_secs_f64() * 1000.0 / mb as f64;
+        println!("{:>4}MB {:>7.1}ms/MB {:>8}KB {:>7.1}ms/MB {:>8}KB", mb,
+            per_mb(stream_time), stream_peak / 1024,
+            per_mb(compile_time), compile_peak / 1024);
+    }
+}
@@ -1,5 +1,6 @@
-use markdown_compiler::compile_markdown;
-use std::io::{self, Read};
+use markdown_compiler::{parse_markdown_with_errors, render_diagnostics, render_to_io, ColorMode};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
 
 /// Usage `cargo r --example demo < ./examples/input.txt`
 fn main() -> io::Result<()> {
@@ -7,11 +8,14 @@ fn main() -> io::Result<()> {
     let mut source = String::new(); 
     std::io::stdin().read_to_string(&mut source)?;
 
-    // Compile it!
-    let resulting_html = compile_markdown(&source);
+    // Compile it! The errors are shown but the html is still written
+    let (document, errors) = parse_markdown_with_errors(&source);
+    eprint!("{}", render_diagnostics(&errors, "<stdin>", &source, ColorMode::Ansi));
 
-    // Write it to `./result.html`
-    std::fs::write("result.html", resulting_html)?;
+    // Write it to `./result.html` as it's generated
+    let mut file = BufWriter::new(File::create("result.html")?);
+    render_to_io(&document, &mut file)?;
+    file.flush()?;
 
     Ok(())
 }
\ No newline at end of file
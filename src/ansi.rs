@@ -0,0 +1,628 @@
+//! The rendering of a document for the terminal, with the ANSI escape codes
+//! of the styles and the colors, to preview markdown in a console
+
+use std::fmt::{self, Write};
+use std::mem;
+
+use super::ast::{self, Alignment, Block, Document, Inline, TableCell};
+use super::compiler::{render_to_fmt_with_renderer, HtmlOptions};
+use super::errors::ColorMode;
+use super::highlight::Highlighters;
+use super::render::{RenderContext, Renderer};
+use super::text::Lines;
+
+/// How the code blocks are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeStyle {
+    /// Inside a box with the name of their language
+    Boxed,
+    /// Indented and dimmed
+    Dimmed,
+}
+
+/// Options of the terminal output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnsiOptions {
+    /// The columns of the terminal, the paragraphs are wrapped to them.
+    /// `None` keeps each paragraph in a single line
+    pub width: Option<usize>,
+
+    /// `ColorMode::Plain` doesn't write any escape code, for the terminals
+    /// without colors or the output that isn't a terminal. The links have
+    /// their url after them instead
+    pub color: ColorMode,
+
+    pub code_style: CodeStyle,
+}
+
+impl Default for AnsiOptions {
+    fn default() -> Self {
+        AnsiOptions { width: Some(80), color: ColorMode::Ansi, code_style: CodeStyle::Boxed }
+    }
+}
+
+/// A run of text with the same style, the SGR parameters of its escape
+/// code and the url of the link it's in
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Piece {
+    sgr: String,
+    link: Option<String>,
+    text: String,
+}
+
+impl Piece {
+    /// The style of the text between two pieces, the SGR parameters both
+    /// start with and their link if it's the same
+    fn between(&self, other: &Piece) -> Piece {
+        let sgr: Vec<&str> = self.sgr.split(';').zip(other.sgr.split(';'))
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a)
+            .collect();
+        let link = self.link.clone().filter(|_| self.link == other.link);
+        Piece { sgr: sgr.join(";"), link, text: String::new() }
+    }
+}
+
+/// Adds the `text` to the `pieces`, in the last one if it has the same
+/// style. The control chars are replaced, so the text can't have its own
+/// escape codes
+fn push_piece(pieces: &mut Vec<Piece>, sgr: &str, link: Option<&str>, text: &str) {
+    let text = text.chars()
+        .map(|ch| if ch.is_control() && ch != '\n' && ch != '\t' { '\u{FFFD}' } else { ch });
+    match pieces.last_mut() {
+        Some(last) if last.sgr == sgr && last.link.as_deref() == link => last.text.extend(text),
+        _ => pieces.push(Piece {
+            sgr: sgr.to_owned(),
+            link: link.map(str::to_owned),
+            text: text.collect(),
+        }),
+    }
+}
+
+/// The chars of the `pieces` seen in the terminal
+fn visible_len(pieces: &[Piece]) -> usize {
+    pieces.iter().map(|piece| piece.text.chars().count()).sum()
+}
+
+/// The lines of some pieces as they are wrapped, see `wrap`
+struct Wrap {
+    width: Option<usize>,
+    lines: Vec<Vec<Piece>>,
+    line: Vec<Piece>,
+    len: usize,
+    word: Vec<Piece>,
+    word_len: usize,
+}
+
+impl Wrap {
+    /// Adds the word to the line, or to a new one if it doesn't fit
+    fn end_word(&mut self) {
+        if self.word.is_empty() {
+            return;
+        }
+        if self.len > 0 && self.width.is_some_and(|width| self.len + 1 + self.word_len > width) {
+            self.lines.push(mem::take(&mut self.line));
+            self.len = 0;
+        }
+        if let (Some(last), Some(first)) = (self.line.last(), self.word.first()) {
+            let space = last.between(first);
+            push_piece(&mut self.line, &space.sgr, space.link.as_deref(), " ");
+            self.len += 1;
+        }
+        for piece in self.word.drain(..) {
+            push_piece(&mut self.line, &piece.sgr, piece.link.as_deref(), &piece.text);
+        }
+        self.len += mem::take(&mut self.word_len);
+    }
+
+    fn end_line(&mut self) {
+        self.end_word();
+        self.lines.push(mem::take(&mut self.line));
+        self.len = 0;
+    }
+}
+
+/// Splits the `pieces` in lines of at most `width` chars, like the plain
+/// text. The space between two words has the style they share, so an
+/// underline isn't cut
+fn wrap(pieces: &[Piece], width: Option<usize>) -> Vec<Vec<Piece>> {
+    let mut wrap = Wrap {
+        width,
+        lines: Vec::new(),
+        line: Vec::new(),
+        len: 0,
+        word: Vec::new(),
+        word_len: 0,
+    };
+    for piece in pieces {
+        for ch in piece.text.chars() {
+            match ch {
+                '\n' => wrap.end_line(),
+                _ if ch.is_whitespace() => wrap.end_word(),
+                _ => {
+                    let mut bytes = [0; 4];
+                    push_piece(&mut wrap.word, &piece.sgr, piece.link.as_deref(),
+                        ch.encode_utf8(&mut bytes));
+                    wrap.word_len += 1;
+                }
+            }
+        }
+    }
+    wrap.end_line();
+    wrap.lines
+}
+
+/// The SGR parameters of the tokens of the highlighted code by their class
+fn class_sgr(class: &str) -> &'static str {
+    match class {
+        "kw" | "pp" => "35",
+        "ty" | "lt" => "33",
+        "str" | "ch" => "32",
+        "num" => "36",
+        "com" => "2;3",
+        "mac" => "34",
+        "attr" => "2",
+        _ => "",
+    }
+}
+
+/// The renderer of the terminal output. The headings are bold and colored,
+/// the emphasis is italic, the code blocks are boxed or dimmed and
+/// highlighted, the lists have `•` or their number and the links are OSC 8
+/// hyperlinks
+#[derive(Debug, Clone, Default)]
+pub struct AnsiRenderer {
+    options: AnsiOptions,
+
+    /// The text of the inlines of the block being rendered
+    text: Vec<Piece>,
+
+    /// The SGR parameters of the inlines the text is in
+    style: Vec<&'static str>,
+
+    /// The url of the link the text is in
+    link: Option<String>,
+
+    lines: Lines,
+}
+
+impl AnsiRenderer {
+    pub fn new(options: AnsiOptions) -> Self {
+        AnsiRenderer { options, ..AnsiRenderer::default() }
+    }
+
+    fn color(&self) -> bool {
+        self.options.color == ColorMode::Ansi
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let sgr = self.style.join(";");
+        push_piece(&mut self.text, &sgr, self.link.as_deref(), text);
+    }
+
+    /// Renders the `children` with the `sgr` style
+    fn styled(&mut self, ctx: &mut RenderContext, sgr: &'static str, children: &[Inline])
+        -> fmt::Result {
+        self.style.push(sgr);
+        self.inlines(ctx, children)?;
+        self.style.pop();
+        Ok(())
+    }
+
+    /// The text of the `inlines` with their styles
+    fn inline_pieces(&mut self, ctx: &mut RenderContext, inlines: &[Inline])
+        -> Result<Vec<Piece>, fmt::Error> {
+        self.text.clear();
+        self.inlines(ctx, inlines)?;
+        Ok(mem::take(&mut self.text))
+    }
+
+    /// The `pieces` with the escape codes of their styles and links
+    fn paint(&self, pieces: &[Piece]) -> String {
+        let mut line = String::new();
+        for piece in pieces {
+            if !self.color() {
+                line.push_str(&piece.text);
+                continue;
+            }
+            if let Some(url) = &piece.link {
+                line.push_str(&format!("\x1b]8;;{}\x1b\\", url));
+            }
+            match piece.sgr.as_str() {
+                "" => line.push_str(&piece.text),
+                sgr => line.push_str(&format!("\x1b[{}m{}\x1b[0m", sgr, piece.text)),
+            }
+            if piece.link.is_some() {
+                line.push_str("\x1b]8;;\x1b\\");
+            }
+        }
+        line
+    }
+
+    /// Writes the `text` alone in a line with the `sgr` style
+    fn write_styled(&mut self, ctx: &mut RenderContext, sgr: &str, text: &str) -> fmt::Result {
+        let mut pieces = Vec::new();
+        push_piece(&mut pieces, sgr, None, text);
+        let line = self.paint(&pieces);
+        self.lines.write_line(ctx, &line)
+    }
+}
+
+impl Renderer for AnsiRenderer {
+    fn document(&mut self, ctx: &mut RenderContext, blocks: &[Block]) -> fmt::Result {
+        self.lines.join();
+        blocks.iter().try_for_each(|block| self.block(ctx, block))
+    }
+
+    /// Without colors the headings of level 1 and 2 are underlined with `=`
+    /// and `-` and the deeper ones start with their `###` as in the plain text
+    fn heading(&mut self, ctx: &mut RenderContext, level: usize, content: &[Inline],
+        _: Option<&str>) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        self.style.push(match level {
+            1 => "1;4;35",
+            2 => "1;4;34",
+            3 => "1;36",
+            _ => "1",
+        });
+        let mut pieces = self.inline_pieces(ctx, content)?;
+        self.style.pop();
+        if level > 2 && !self.color() {
+            let text = format!("{} ", "#".repeat(level));
+            pieces.insert(0, Piece { sgr: String::new(), link: None, text });
+        }
+        let lines = wrap(&pieces, self.lines.width(self.options.width));
+        for line in &lines {
+            let line = self.paint(line);
+            self.lines.write_line(ctx, &line)?;
+        }
+        let len = lines.iter().map(|line| visible_len(line)).max().unwrap_or(0);
+        match level {
+            1 if !self.color() => self.lines.write_line(ctx, &"=".repeat(len)),
+            2 if !self.color() => self.lines.write_line(ctx, &"-".repeat(len)),
+            _ => Ok(()),
+        }
+    }
+
+    fn paragraph(&mut self, ctx: &mut RenderContext, content: &[Inline]) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let pieces = self.inline_pieces(ctx, content)?;
+        for line in wrap(&pieces, self.lines.width(self.options.width)) {
+            let line = self.paint(&line);
+            self.lines.write_line(ctx, &line)?;
+        }
+        Ok(())
+    }
+
+    /// The code isn't wrapped, it's highlighted if there is a highlighter
+    /// of its language
+    fn code_block(&mut self, ctx: &mut RenderContext, lang: Option<&str>, code: &str)
+        -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let highlighter = lang.and_then(|l| ctx.highlighters().get(l));
+        let tokens = match highlighter {
+            Some((_, highlighter)) if self.color() => highlighter.highlight(code),
+            _ => vec![(None, code.to_owned())],
+        };
+        let base = match self.options.code_style {
+            CodeStyle::Boxed => "",
+            CodeStyle::Dimmed => "2",
+        };
+
+        // The pieces of each line of the code
+        let mut lines = vec![Vec::new()];
+        for (class, text) in &tokens {
+            let sgr = [base, class.map_or("", class_sgr)].iter()
+                .filter(|sgr| !sgr.is_empty())
+                .copied()
+                .collect::<Vec<_>>()
+                .join(";");
+            for (i, part) in text.split('\n').enumerate() {
+                if i > 0 {
+                    lines.push(Vec::new());
+                }
+                if !part.is_empty() {
+                    push_piece(lines.last_mut().unwrap(), &sgr, None, part);
+                }
+            }
+        }
+        if code.ends_with('\n') {
+            lines.pop();
+        }
+
+        match self.options.code_style {
+            CodeStyle::Boxed => {
+                let name = highlighter.map(|(name, _)| name).or(lang);
+                let name_len = name.map_or(0, |name| name.chars().count() + 3);
+                let mut bar = lines.iter().map(|line| visible_len(line)).max().unwrap_or(0)
+                    .max(name_len) + 2;
+                if let Some(width) = self.lines.width(self.options.width) {
+                    bar = bar.min(width.saturating_sub(1));
+                }
+                let top = match name {
+                    Some(name) => {
+                        format!("┌─ {} {}", name, "─".repeat(bar.saturating_sub(name_len)))
+                    }
+                    None => format!("┌{}", "─".repeat(bar)),
+                };
+                self.write_styled(ctx, "2", &top)?;
+                let mut border = Vec::new();
+                push_piece(&mut border, "2", None, "│");
+                let border = self.paint(&border);
+                for line in &lines {
+                    let line = if line.is_empty() {
+                        border.clone()
+                    } else {
+                        format!("{} {}", border, self.paint(line))
+                    };
+                    self.lines.write_line(ctx, &line)?;
+                }
+                self.write_styled(ctx, "2", &format!("└{}", "─".repeat(bar)))
+            }
+            CodeStyle::Dimmed => {
+                for line in &lines {
+                    let line = if line.is_empty() {
+                        String::new()
+                    } else {
+                        format!("    {}", self.paint(line))
+                    };
+                    self.lines.write_line(ctx, &line)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn list(&mut self, ctx: &mut RenderContext, start: Option<usize>, tight: bool,
+        items: &[Vec<Block>]) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 && !tight {
+                ctx.write_char('\n')?;
+            }
+            let indent = self.lines.start_item(match start {
+                Some(start) => format!("{}. ", start + i),
+                None => "• ".to_owned(),
+            });
+            self.list_item(ctx, item, tight)?;
+            self.lines.end(ctx, indent)?;
+        }
+        Ok(())
+    }
+
+    fn list_item(&mut self, ctx: &mut RenderContext, blocks: &[Block], tight: bool)
+        -> fmt::Result {
+        for block in blocks {
+            if tight {
+                self.lines.join();
+            }
+            self.block(ctx, block)?;
+        }
+        Ok(())
+    }
+
+    fn quote(&mut self, ctx: &mut RenderContext, blocks: &[Block]) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let indent = self.lines.start("│ ");
+        for block in blocks {
+            self.block(ctx, block)?;
+        }
+        self.lines.end(ctx, indent)
+    }
+
+    /// The columns are padded to be aligned, the header is bold
+    fn table(&mut self, ctx: &mut RenderContext, alignments: &[Alignment], header: &[TableCell],
+        rows: &[Vec<TableCell>]) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let mut cells = Vec::new();
+        for (i, row) in std::iter::once(header).chain(rows.iter().map(Vec::as_slice)).enumerate() {
+            if i == 0 {
+                self.style.push("1");
+            }
+            let mut texts = Vec::new();
+            for cell in row {
+                // Each cell in a single line
+                let pieces = self.inline_pieces(ctx, &cell.content)?;
+                let mut text = Vec::new();
+                for line in wrap(&pieces, None) {
+                    if !text.is_empty() {
+                        push_piece(&mut text, "", None, " ");
+                    }
+                    text.extend(line);
+                }
+                texts.push(text);
+            }
+            if i == 0 {
+                self.style.pop();
+            }
+            cells.push(texts);
+        }
+        let widths: Vec<usize> = (0..alignments.len())
+            .map(|i| cells.iter().filter_map(|row| row.get(i)).map(|cell| visible_len(cell))
+                .max().unwrap_or(0))
+            .collect();
+
+        for (i, row) in cells.iter().enumerate() {
+            let mut line = String::new();
+            for (j, ((cell, width), alignment)) in row.iter().zip(&widths).zip(alignments)
+                .enumerate() {
+                let padding = width - visible_len(cell);
+                let before = match alignment {
+                    Alignment::Right => padding,
+                    Alignment::Center => padding / 2,
+                    _ => 0,
+                };
+                if j > 0 {
+                    line.push_str("  ");
+                }
+                line.push_str(&" ".repeat(before));
+                line.push_str(&self.paint(cell));
+                line.push_str(&" ".repeat(padding - before));
+            }
+            // The last column isn't padded
+            self.lines.write_line(ctx, line.trim_end())?;
+            if i == 0 {
+                let rule: Vec<String> = widths.iter().map(|width| "─".repeat(*width)).collect();
+                self.write_styled(ctx, "2", &rule.join("  "))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn thematic_break(&mut self, ctx: &mut RenderContext) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let width = self.lines.width(self.options.width).unwrap_or(40);
+        self.write_styled(ctx, "2", &"─".repeat(width))
+    }
+
+    fn table_of_contents(&mut self, _: &mut RenderContext) -> fmt::Result {
+        Ok(())
+    }
+
+    fn text(&mut self, _: &mut RenderContext, text: &str) -> fmt::Result {
+        self.push_text(text);
+        Ok(())
+    }
+
+    fn code(&mut self, _: &mut RenderContext, code: &str) -> fmt::Result {
+        self.style.push("33");
+        self.push_text(code);
+        self.style.pop();
+        Ok(())
+    }
+
+    fn emphasis(&mut self, ctx: &mut RenderContext, children: &[Inline]) -> fmt::Result {
+        self.styled(ctx, "3", children)
+    }
+
+    fn strong(&mut self, ctx: &mut RenderContext, children: &[Inline]) -> fmt::Result {
+        self.styled(ctx, "1", children)
+    }
+
+    /// Without colors the url is after the text, if it isn't the same
+    fn link(&mut self, ctx: &mut RenderContext, children: &[Inline], url: &str,
+        _: Option<&str>) -> fmt::Result {
+        if !self.color() {
+            self.inlines(ctx, children)?;
+            if ast::text(children) != url {
+                self.push_text(&format!(" ({})", url));
+            }
+            return Ok(());
+        }
+        let url: String = url.chars().filter(|ch| !ch.is_control()).collect();
+        let outer = self.link.replace(url);
+        self.styled(ctx, "4;34", children)?;
+        self.link = outer;
+        Ok(())
+    }
+
+    /// The alt of the image, linking to it
+    fn image(&mut self, _: &mut RenderContext, alt: &str, src: &str, _: Option<&str>)
+        -> fmt::Result {
+        let src: String = src.chars().filter(|ch| !ch.is_control()).collect();
+        let outer = self.link.replace(src);
+        self.style.push("2");
+        self.push_text(alt);
+        self.style.pop();
+        self.link = outer;
+        Ok(())
+    }
+
+    fn soft_break(&mut self, _: &mut RenderContext) -> fmt::Result {
+        self.push_text(" ");
+        Ok(())
+    }
+
+    fn hard_break(&mut self, _: &mut RenderContext) -> fmt::Result {
+        self.push_text("\n");
+        Ok(())
+    }
+}
+
+/// The `document` for the terminal with the `options` and the default
+/// highlighters, see `AnsiRenderer`. Other highlighters can be given
+/// rendering it with `render_to_fmt_with_renderer`
+pub fn render_ansi(document: &Document, options: &AnsiOptions) -> String {
+    let mut text = String::new();
+    let mut renderer = AnsiRenderer::new(options.clone());
+    // Writing into a `String` can't fail
+    let _ = render_to_fmt_with_renderer(document, &mut text, &mut renderer,
+        &Highlighters::default(), &HtmlOptions::default());
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BlockKind, InlineKind};
+    use crate::span::Span;
+    use crate::{parse_markdown, TextOptions};
+
+    fn ansi(source: &str, color: ColorMode, code_style: CodeStyle) -> String {
+        render_ansi(&parse_markdown(source), &AnsiOptions { width: Some(20), color, code_style })
+    }
+
+    #[test]
+    fn ansi_tests() {
+        let source = "# Notes *1.0*\nA **bold** [new link](https://a.io) and `x`\n\n\
+            - a\n  - b\n\n> c\n\n```rust\nfn a() {}\n```\n\n| d | e |\n|---|--:|\n| fff | 1 |\n---";
+
+        // Without colors it's plain text that keeps the urls
+        assert_eq!(ansi(source, ColorMode::Plain, CodeStyle::Boxed),
+            "Notes 1.0\n=========\n\nA bold new link\n(https://a.io) and x\n\n• a\n  • b\n\n│ c\n\n\
+            ┌─ rust ────\n│ fn a() {}\n└───────────\n\nd    e\n───  ─\nfff  1\n\n\
+            ────────────────────\n");
+
+        // The styles don't count for the width, the links are OSC 8
+        // hyperlinks and the spaces have the style of their words
+        let colored = ansi(source, ColorMode::Ansi, CodeStyle::Dimmed);
+        assert!(colored.starts_with("\x1b[1;4;35mNotes \x1b[0m\x1b[1;4;35;3m1.0\x1b[0m\n\n\
+            A \x1b[1mbold\x1b[0m \x1b]8;;https://a.io\x1b\\\x1b[4;34mnew link\x1b[0m\x1b]8;;\x1b\\ and\n\
+            \x1b[33mx\x1b[0m\n"), "{:?}", colored);
+        // The code is highlighted and dimmed
+        assert!(colored.contains("\n    \x1b[2;35mfn\x1b[0m\x1b[2m a() {}\x1b[0m\n"), "{:?}", colored);
+        let plain: String = colored.split('\x1b').enumerate()
+            .map(|(i, part)| match i {
+                0 => part,
+                _ if part.starts_with(']') => "",
+                _ if part.starts_with('\\') => &part[1..],
+                _ => part.split_once('m').map_or("", |(_, text)| text),
+            })
+            .collect();
+        assert_eq!(plain, "Notes 1.0\n\nA bold new link and\nx\n\n• a\n  • b\n\n│ c\n\n\
+            \x20   fn a() {}\n\nd    e\n───  ─\nfff  1\n\n────────────────────\n");
+
+        // The deeper headings have their level, the code lines are kept as
+        // they are
+        assert_eq!(ansi("### a\n- ```\n  b  \n\n  ```", ColorMode::Plain, CodeStyle::Boxed),
+            "### a\n\n• ┌─────\n  │ b  \n  │\n  └─────\n");
+
+        // A list first in an item has the bullets of both
+        assert_eq!(ansi("- - a\n\n1. - b", ColorMode::Plain, CodeStyle::Boxed),
+            "• • a\n\n1. • b\n");
+
+        // A row of the ast without all its cells doesn't panic in any renderer,
+        // the missing ones are left out
+        let cell = |text: &str| TableCell {
+            content: vec![Inline::new(InlineKind::Text(text.to_owned().into()), Span::default())],
+            span: Span::default(),
+        };
+        let document = Document { blocks: vec![Block::new(BlockKind::Table {
+            alignments: vec![Alignment::None, Alignment::Right],
+            header: vec![cell("a"), cell("b")],
+            rows: vec![vec![cell("ccc")]],
+        }, Span::default())] };
+        let mut html = String::new();
+        crate::render_to_fmt(&document, &mut html).unwrap();
+        assert_eq!(html, "<table>\n<thead>\n<tr>\n<th>a</th>\n\
+            <th style=\"text-align: right\">b</th>\n</tr>\n</thead>\n<tbody>\n<tr>\n\
+            <td>ccc</td>\n</tr>\n</tbody>\n</table>\n");
+        assert_eq!(crate::render_text(&document, &TextOptions::default()), "a    b\nccc\n");
+        let options = AnsiOptions { color: ColorMode::Plain, ..AnsiOptions::default() };
+        assert_eq!(render_ansi(&document, &options), "a    b\n───  ─\nccc\n");
+
+        // The text can't have its own escape codes
+        assert_eq!(ansi("a\x1b[31m \x07[b](c\x1b)", ColorMode::Ansi, CodeStyle::Boxed),
+            "a\u{FFFD}[31m \u{FFFD}\x1b]8;;c\x1b\\\x1b[4;34mb\x1b[0m\x1b]8;;\x1b\\\n");
+    }
+}
@@ -0,0 +1,299 @@
+//! The tree of a parsed markdown document, a `Document` has `Block`s, like
+//! the headings, the paragraphs or the lists, and the blocks with text have
+//! `Inline` children with its format. Every node has the span of the source
+//! it was parsed from. The text of the nodes is borrowed from the source
+//! when it's the same, `into_owned` gives a tree that doesn't need it
+
+use std::borrow::Cow;
+
+use super::span::Span;
+
+/// A parsed markdown document
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Document<'a> {
+    /// The blocks in the order of the source
+    pub blocks: Vec<Block<'a>>,
+}
+
+impl Document<'_> {
+    /// The same document but owning all its text
+    pub fn into_owned(self) -> Document<'static> {
+        Document { blocks: owned_blocks(self.blocks) }
+    }
+}
+
+/// A block of the document, it starts in its own line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block<'a> {
+    pub kind: BlockKind<'a>,
+    pub span: Span,
+}
+
+impl<'a> Block<'a> {
+    pub fn new(kind: BlockKind<'a>, span: Span) -> Self {
+        Block { kind, span }
+    }
+
+    /// The same block but owning all its text
+    pub fn into_owned(self) -> Block<'static> {
+        let kind = match self.kind {
+            BlockKind::Heading { level, content, id } => BlockKind::Heading {
+                level,
+                content: owned_inlines(content),
+                id: id.map(owned),
+            },
+            BlockKind::Paragraph(content) => BlockKind::Paragraph(owned_inlines(content)),
+            BlockKind::CodeBlock { lang, code } => {
+                BlockKind::CodeBlock { lang: lang.map(owned), code: owned(code) }
+            }
+            BlockKind::List { start, tight, items } => BlockKind::List {
+                start,
+                tight,
+                items: items.into_iter().map(owned_blocks).collect(),
+            },
+            BlockKind::Quote(blocks) => BlockKind::Quote(owned_blocks(blocks)),
+            BlockKind::Table { alignments, header, rows } => BlockKind::Table {
+                alignments,
+                header: header.into_iter().map(TableCell::into_owned).collect(),
+                rows: rows.into_iter()
+                    .map(|row| row.into_iter().map(TableCell::into_owned).collect())
+                    .collect(),
+            },
+            BlockKind::ThematicBreak => BlockKind::ThematicBreak,
+            BlockKind::TableOfContents => BlockKind::TableOfContents,
+        };
+        Block::new(kind, self.span)
+    }
+}
+
+/// The kinds of blocks with their content
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockKind<'a> {
+    /// `# heading` or a setext heading, with its `level` from 1 to 6 and the
+    /// `id` given after its text, `# heading {#id}`
+    Heading { level: usize, content: Vec<Inline<'a>>, id: Option<Cow<'a, str>> },
+
+    /// Consecutive lines of text
+    Paragraph(Vec<Inline<'a>>),
+
+    /// A fenced code block with the language of its info string, `rust` in
+    /// ```` ```rust ````
+    CodeBlock { lang: Option<Cow<'a, str>>, code: Cow<'a, str> },
+
+    /// `- item`, `+ item` or `* item`, or `1. item` or `1) item` when it has
+    /// the `start` number. Each item has its own blocks, in a tight list there
+    /// are no blank lines between the items nor between their blocks
+    List { start: Option<usize>, tight: bool, items: Vec<Vec<Block<'a>>> },
+
+    /// `> quote`, its lines start with `>` and have any blocks, even other
+    /// quotes. The lines that continue a paragraph can omit the `>`
+    Quote(Vec<Block<'a>>),
+
+    /// A table, its `header` row is followed by a `|---|:--:|` row with the
+    /// alignment of each column and then by the body `rows`. Every row has a
+    /// cell for each column, the missing cells are empty and the extra ones
+    /// are dropped
+    Table {
+        alignments: Vec<Alignment>,
+        header: Vec<TableCell<'a>>,
+        rows: Vec<Vec<TableCell<'a>>>,
+    },
+
+    /// `---`, `***` or `___`, also with spaces like `* * *`
+    ThematicBreak,
+
+    /// A `[TOC]` or `[[toc]]` line alone, where the table of contents of the
+    /// document goes
+    TableOfContents,
+}
+
+/// The alignment of a table column, `:--` is left, `:-:` center, `--:`
+/// right and `---` none
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+/// A cell of a table, between `|`s
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableCell<'a> {
+    pub content: Vec<Inline<'a>>,
+    pub span: Span,
+}
+
+impl TableCell<'_> {
+    /// The same cell but owning all its text
+    pub fn into_owned(self) -> TableCell<'static> {
+        TableCell { content: owned_inlines(self.content), span: self.span }
+    }
+}
+
+/// The content of a block with its format
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inline<'a> {
+    pub kind: InlineKind<'a>,
+    pub span: Span,
+}
+
+impl<'a> Inline<'a> {
+    pub fn new(kind: InlineKind<'a>, span: Span) -> Self {
+        Inline { kind, span }
+    }
+
+    /// The text of the inline without any format, the line breaks are `\n`
+    pub fn text(&self) -> String {
+        match &self.kind {
+            InlineKind::Text(text) | InlineKind::Code(text) => text.to_string(),
+            InlineKind::Emphasis(children)
+            | InlineKind::Strong(children)
+            | InlineKind::Link { children, .. } => text(children),
+            InlineKind::Image { alt, .. } => alt.to_string(),
+            InlineKind::SoftBreak | InlineKind::HardBreak => "\n".to_owned(),
+        }
+    }
+
+    /// The same inline but owning all its text
+    pub fn into_owned(self) -> Inline<'static> {
+        let kind = match self.kind {
+            InlineKind::Text(text) => InlineKind::Text(owned(text)),
+            InlineKind::Code(code) => InlineKind::Code(owned(code)),
+            InlineKind::Emphasis(children) => InlineKind::Emphasis(owned_inlines(children)),
+            InlineKind::Strong(children) => InlineKind::Strong(owned_inlines(children)),
+            InlineKind::Link { children, url, title, label } => InlineKind::Link {
+                children: owned_inlines(children),
+                url: owned(url),
+                title: title.map(owned),
+                label: label.map(owned),
+            },
+            InlineKind::Image { alt, src, title, label } => InlineKind::Image {
+                alt: owned(alt),
+                src: owned(src),
+                title: title.map(owned),
+                label: label.map(owned),
+            },
+            InlineKind::SoftBreak => InlineKind::SoftBreak,
+            InlineKind::HardBreak => InlineKind::HardBreak,
+        };
+        Inline::new(kind, self.span)
+    }
+}
+
+/// The kinds of inlines with their content
+#[derive(Debug, Clone, PartialEq)]
+pub enum InlineKind<'a> {
+    Text(Cow<'a, str>),
+
+    /// `` `code` ``
+    Code(Cow<'a, str>),
+
+    /// `*text*` or `_text_`
+    Emphasis(Vec<Inline<'a>>),
+
+    /// `**text**` or `__text__`
+    Strong(Vec<Inline<'a>>),
+
+    /// `[text](url "title")`, or a reference link `[text][label]`,
+    /// `[text][]` or `[text]` with a `[label]: url "title"` definition
+    /// somewhere in the document
+    Link {
+        children: Vec<Inline<'a>>,
+        url: Cow<'a, str>,
+        title: Option<Cow<'a, str>>,
+        label: Option<Cow<'a, str>>,
+    },
+
+    /// `![alt](src "title")`, or a reference image `![alt][label]`,
+    /// `![alt][]` or `![alt]`
+    Image {
+        alt: Cow<'a, str>,
+        src: Cow<'a, str>,
+        title: Option<Cow<'a, str>>,
+        label: Option<Cow<'a, str>>,
+    },
+
+    /// A line break inside a paragraph
+    SoftBreak,
+
+    /// A line ending with 2 spaces or a `\` inside a paragraph
+    HardBreak,
+}
+
+/// The text of some inlines without any format, see `Inline::text`
+pub fn text(inlines: &[Inline]) -> String {
+    inlines.iter().map(Inline::text).collect()
+}
+
+fn owned(text: Cow<str>) -> Cow<'static, str> {
+    Cow::Owned(text.into_owned())
+}
+
+fn owned_inlines(inlines: Vec<Inline>) -> Vec<Inline<'static>> {
+    inlines.into_iter().map(Inline::into_owned).collect()
+}
+
+fn owned_blocks(blocks: Vec<Block>) -> Vec<Block<'static>> {
+    blocks.into_iter().map(Block::into_owned).collect()
+}
+
+/// The tests compare the trees without the spans
+#[cfg(test)]
+impl<'a> Block<'a> {
+    /// The block with all the spans empty
+    pub(crate) fn without_spans(self) -> Block<'a> {
+        let strip = |inlines: Vec<Inline<'a>>| -> Vec<Inline<'a>> {
+            inlines.into_iter().map(Inline::without_spans).collect()
+        };
+        let kind = match self.kind {
+            BlockKind::Heading { level, content, id } => {
+                BlockKind::Heading { level, content: strip(content), id }
+            }
+            BlockKind::Paragraph(content) => BlockKind::Paragraph(strip(content)),
+            BlockKind::List { start, tight, items } => BlockKind::List {
+                start,
+                tight,
+                items: items.into_iter()
+                    .map(|item| item.into_iter().map(Block::without_spans).collect())
+                    .collect(),
+            },
+            BlockKind::Quote(blocks) => {
+                BlockKind::Quote(blocks.into_iter().map(Block::without_spans).collect())
+            }
+            BlockKind::Table { alignments, header, rows } => {
+                let strip_row = |row: Vec<TableCell<'a>>| -> Vec<TableCell<'a>> {
+                    row.into_iter()
+                        .map(|cell| TableCell { content: strip(cell.content), span: Span::default() })
+                        .collect()
+                };
+                BlockKind::Table {
+                    alignments,
+                    header: strip_row(header),
+                    rows: rows.into_iter().map(strip_row).collect(),
+                }
+            }
+            kind => kind,
+        };
+        Block::new(kind, Span::default())
+    }
+}
+
+#[cfg(test)]
+impl<'a> Inline<'a> {
+    /// The inline with all the spans empty
+    pub(crate) fn without_spans(self) -> Inline<'a> {
+        let strip = |inlines: Vec<Inline<'a>>| -> Vec<Inline<'a>> {
+            inlines.into_iter().map(Inline::without_spans).collect()
+        };
+        let kind = match self.kind {
+            InlineKind::Emphasis(children) => InlineKind::Emphasis(strip(children)),
+            InlineKind::Strong(children) => InlineKind::Strong(strip(children)),
+            InlineKind::Link { children, url, title, label } => {
+                InlineKind::Link { children: strip(children), url, title, label }
+            }
+            kind => kind,
+        };
+        Inline::new(kind, Span::default())
+    }
+}
@@ -1,35 +1,236 @@
-use std::fmt;
-use super::parser::{CSLanguage, MDValue, MDParser};
+use std::fmt::{self, Write};
+use std::io;
+use super::ast::{Block, Document, Inline};
+use super::highlight::Highlighters;
+use super::render::{HtmlRenderer, RenderContext, Renderer};
+use super::toc::TocOptions;
 
-/// The compilation of the MDValue would be just implementing the Debug Trait
-impl fmt::Display for MDValue {
+/// How the alignment of the table columns is given in the html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TableAlignment {
+    /// `style="text-align: center"`
+    Style,
+    /// A class with the alignment after the prefix, `Class("text-")` gives
+    /// `class="text-center"`
+    Class(String),
+}
+
+/// Options of the generated html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtmlOptions {
+    pub table_alignment: TableAlignment,
+
+    /// If the headings without an explicit `{#id}` get the slug of their
+    /// text as id, see `Slugger`. They always get it if there is a table of
+    /// contents, that links to them
+    pub heading_ids: bool,
+
+    /// The text of a link to the heading itself after its text, like `#` or
+    /// `¶`, the headings without id don't have it
+    pub heading_anchor: Option<String>,
+
+    /// The levels of the headings in the table of contents of the `[TOC]`
+    /// markers, it links to their ids
+    pub toc: TocOptions,
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        HtmlOptions {
+            table_alignment: TableAlignment::Style,
+            heading_ids: true,
+            heading_anchor: None,
+            toc: TocOptions::default(),
+        }
+    }
+}
+
+/// The compilation of the Document would be just implementing the Display
+/// Trait, with the default highlighters and options
+impl fmt::Display for Document<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut value = String::new();
-        match self {
-            MDValue::BigHeader(text) => {
-                value.push_str(&format!("<h1>{}</h1>", text));
-            },
-            MDValue::MediumHeader(text) => {
-                value.push_str(&format!("<h2>{}</h2>", text));
-            }
-            MDValue::SmallHeader(text) => {
-                value.push_str(&format!("<h3>{}</h3>", text));
-            }
-            MDValue::VerySmallHeader(text) => {
-                value.push_str(&format!("<h4>{}</h4>", text));
-            }
-            // Maybe use prettier library for syntax highlighting
-            MDValue::CodeSnippet((lang, text)) => {
-                value.push_str(&format!("<code>{}<code>", text));
-            }
-            MDValue::NewLine => {
-                value.push('\n');
+        render_to_fmt(self, f)
+    }
+}
+
+impl fmt::Display for Block<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (highlighters, options) = (Highlighters::default(), HtmlOptions::default());
+        let blocks = std::slice::from_ref(self);
+        HtmlRenderer.block(&mut RenderContext::new(f, blocks, &highlighters, &options), self)
+    }
+}
+
+impl fmt::Display for Inline<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (highlighters, options) = (Highlighters::default(), HtmlOptions::default());
+        HtmlRenderer.inline(&mut RenderContext::new(f, &[], &highlighters, &options), self)
+    }
+}
+
+/// Writes the html of the `document` into `out` as it's generated, with the
+/// default highlighters and options
+pub fn render_to_fmt<W: Write + ?Sized>(document: &Document, out: &mut W) -> fmt::Result {
+    render_to_fmt_with_options(document, out, &Highlighters::default(), &HtmlOptions::default())
+}
+
+/// Like `render_to_fmt` but the code blocks are highlighted with the
+/// `highlighters` and the html is generated with the `options`. Each block is
+/// in its own line
+pub fn render_to_fmt_with_options<W: Write + ?Sized>(document: &Document, out: &mut W,
+    highlighters: &Highlighters, options: &HtmlOptions) -> fmt::Result {
+    render_to_fmt_with_renderer(document, out, &mut HtmlRenderer, highlighters, options)
+}
+
+/// Like `render_to_fmt_with_options` but each node is rendered by the hooks
+/// of the `renderer`, see `Renderer`
+pub fn render_to_fmt_with_renderer<R, W>(document: &Document, mut out: &mut W, renderer: &mut R,
+    highlighters: &Highlighters, options: &HtmlOptions) -> fmt::Result
+    where R: Renderer + ?Sized, W: Write + ?Sized {
+    let mut context = RenderContext::new(&mut out, &document.blocks, highlighters, options);
+    renderer.document(&mut context, &document.blocks)
+}
+
+/// Writes the html of the `document` into `out`, like a file or a socket,
+/// with the default highlighters and options. Nothing is buffered, so a slow
+/// `out` should be wrapped in a `BufWriter`
+pub fn render_to_io<W: io::Write + ?Sized>(document: &Document, out: &mut W) -> io::Result<()> {
+    render_to_io_with_options(document, out, &Highlighters::default(), &HtmlOptions::default())
+}
+
+/// Like `render_to_io` but with the `highlighters` and the `options` of
+/// `render_to_fmt_with_options`
+pub fn render_to_io_with_options<W: io::Write + ?Sized>(document: &Document, out: &mut W,
+    highlighters: &Highlighters, options: &HtmlOptions) -> io::Result<()> {
+    render_to_io_with_renderer(document, out, &mut HtmlRenderer, highlighters, options)
+}
+
+/// Like `render_to_io_with_options` but with the `renderer` of
+/// `render_to_fmt_with_renderer`
+pub fn render_to_io_with_renderer<R, W>(document: &Document, out: &mut W, renderer: &mut R,
+    highlighters: &Highlighters, options: &HtmlOptions) -> io::Result<()>
+    where R: Renderer + ?Sized, W: io::Write + ?Sized {
+    let mut writer = IoWriter { out, error: Ok(()) };
+    let result = render_to_fmt_with_renderer(document, &mut writer, renderer, highlighters,
+        options);
+    if result.is_err() {
+        writer.error?;
+        return Err(io::Error::other("the html couldn't be formatted"));
+    }
+    Ok(())
+}
+
+/// Adapter to write into an `io::Write` as a `fmt::Write`, it keeps the io
+/// error that `fmt::Error` can't have
+struct IoWriter<'a, W: ?Sized> {
+    out: &'a mut W,
+    error: io::Result<()>,
+}
+
+impl<W: io::Write + ?Sized> Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Err(error);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BlockKind, InlineKind};
+    use crate::span::Span;
+
+    fn block(kind: BlockKind) -> Block {
+        Block::new(kind, Span::default())
+    }
+
+    fn inline(kind: InlineKind) -> Inline {
+        Inline::new(kind, Span::default())
+    }
+
+    #[test]
+    fn escaping_tests() {
+        let markup = "<script>alert('x')</script> & \"q\"";
+        let escaped = "&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; &amp; &quot;q&quot;";
+        let text = || vec![inline(InlineKind::Text(markup.into()))];
+
+        // Every sink of text
+        let blocks = vec![
+            block(BlockKind::Heading { level: 1, content: text(), id: None }),
+            block(BlockKind::Paragraph(text())),
+            block(BlockKind::Paragraph(vec![inline(InlineKind::Emphasis(text()))])),
+            block(BlockKind::Paragraph(vec![
+                inline(InlineKind::Strong(vec![inline(InlineKind::Emphasis(text()))])),
+            ])),
+            block(BlockKind::Paragraph(vec![inline(InlineKind::Code(markup.into()))])),
+            block(BlockKind::CodeBlock { lang: None, code: markup.into() }),
+        ];
+        for block in blocks {
+            let html = block.to_string();
+            assert!(html.contains(escaped), "{}", html);
+            assert!(!html.contains("<script>"), "{}", html);
+        }
+
+        // Also the highlighted code
+        let code = |lang: &'static str, code: &'static str| block(BlockKind::CodeBlock {
+            lang: Some(lang.into()),
+            code: code.into(),
+        });
+        let html = code("rust", markup).to_string();
+        assert!(!html.contains("<script>") && !html.contains("\"q\""), "{}", html);
+        assert_eq!(code("\"><b>", "").to_string(),
+            "<pre><code class=\"language-&quot;&gt;&lt;b&gt;\"></code></pre>");
+
+        // The urls can't close their attribute
+        let link = inline(InlineKind::Link {
+            children: vec![inline(InlineKind::Text("<b>".into()))],
+            url: "/a b\"onclick='x'&<é>".into(),
+            title: Some("\" onmouseover=\"x".into()),
+            label: None,
+        });
+        assert_eq!(link.to_string(), "<a href=\"/a%20b%22onclick=&#39;x&#39;&amp;%3C%C3%A9%3E\" \
+            title=\"&quot; onmouseover=&quot;x\">&lt;b&gt;</a>");
+        let image = inline(InlineKind::Image {
+            alt: "\"><script>".into(),
+            src: "x.png\"".into(),
+            title: None,
+            label: Some("l".into()),
+        });
+        assert_eq!(image.to_string(),
+            "<img src=\"x.png%22\" alt=\"&quot;&gt;&lt;script&gt;\">");
+    }
+
+    #[test]
+    fn render_tests() {
+        let document = crate::parse_markdown("# Title\n- *a*\n  ```rs\n  1\n  ```\n\n| b |\n|--:|");
+        let html = "<h1 id=\"title\">Title</h1>\n<ul>\n<li><em>a</em>\n\
+            <pre><code class=\"language-rust\"><span class=\"num\">1</span>\n</code></pre>\n\
+            </li>\n</ul>\n<table>\n<thead>\n<tr>\n<th style=\"text-align: right\">b</th>\n\
+            </tr>\n</thead>\n</table>\n";
+
+        // The same html in any sink
+        let mut string = String::new();
+        render_to_fmt(&document, &mut string).unwrap();
+        assert_eq!(string, html);
+        assert_eq!(document.to_string(), html);
+        let mut bytes = Vec::new();
+        render_to_io(&document, &mut bytes).unwrap();
+        assert_eq!(bytes, html.as_bytes());
+
+        // The io errors are given as they are
+        struct Full;
+        impl io::Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::StorageFull, "full"))
             }
-            MDValue::Text(text) => {
-                value.push_str(text);
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
             }
-        };
-        write!(f,"{}",value)
+        }
+        let error = render_to_io(&document, &mut Full).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::StorageFull);
     }
 }
-
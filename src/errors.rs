@@ -1,9 +1,157 @@
-use super::parser::MDValue;
-use super::tokenizer::Token;
-use std::rc::Rc;
-
-/// Representation of all the possible errors originated at 
-pub enum ParserError<'a> {
-    ExpectedButGot((usize, usize), &'a[&'a str], Token),
-    Unexpected((usize, usize), &'a Token),
-}
\ No newline at end of file
+use std::error::Error;
+use std::fmt;
+
+use super::span::Span;
+
+/// Representation of all the possible errors originated at the tokenizer or
+/// the parser, with the span of the source where they were found. They are
+/// not fatal, the source is always compiled doing its best
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParserError {
+    /// A code fence without the closing one, the code lasts until the end
+    /// of the source. Its span is the opening fence
+    UnclosedCodeFence(Span),
+    /// A char that can't be in the html, it's replaced with `U+FFFD`
+    UnexpectedChar(Span, char),
+}
+
+impl ParserError {
+    /// The span of the source with the error
+    pub fn span(&self) -> Span {
+        match self {
+            ParserError::UnclosedCodeFence(span)
+            | ParserError::UnexpectedChar(span, _) => *span,
+        }
+    }
+
+    /// The message of the error, without its position
+    pub fn message(&self) -> String {
+        match self {
+            ParserError::UnclosedCodeFence(_) => "unclosed code fence".to_owned(),
+            ParserError::UnexpectedChar(_, ch) => format!("unexpected char {:?}", ch),
+        }
+    }
+
+    /// A hint of what happened at the span of the error
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            ParserError::UnclosedCodeFence(_) => Some("opened here, it lasts until the end"),
+            ParserError::UnexpectedChar(..) => {
+                Some("this char is replaced with U+FFFD in the html")
+            }
+        }
+    }
+
+    /// Renders the error as a rustc-style diagnostic, with the name of the
+    /// `file`, the line of the `source` with the error underlined and its hint
+    pub fn render(&self, file: &str, source: &str, mode: ColorMode) -> String {
+        let paint = |style: &str, text: &str| match mode {
+            ColorMode::Plain => text.to_owned(),
+            ColorMode::Ansi => format!("\x1b[{}m{}\x1b[0m", style, text),
+        };
+        let span = self.span();
+        let start = span.start.offset.min(source.len());
+
+        // The line with the start of the error, the tabs are one column
+        let line_start = source[..start].rfind(['\n', '\r']).map_or(0, |i| i + 1);
+        let line_end = source[start..].find(['\n', '\r']).map_or(source.len(), |i| start + i);
+        let line = source[line_start..line_end].replace('\t', " ");
+        let column = source[line_start..start].chars().count();
+
+        // The underline until the end of the span or of its line
+        let width = if span.end.line == span.start.line {
+            span.end.column.saturating_sub(span.start.column)
+        } else {
+            line.chars().count().saturating_sub(column)
+        };
+        let carets = "^".repeat(width.max(1));
+
+        let number = (span.start.line + 1).to_string();
+        let gutter = " ".repeat(number.len());
+        let bar = paint("1;34", "|");
+
+        let mut text = format!("{}{}\n", paint("1;31", "error"),
+            paint("1", &format!(": {}", self.message())));
+        text.push_str(&format!("{}{} {}:{}\n", gutter, paint("1;34", "-->"), file, span.start));
+        text.push_str(&format!("{} {}\n", gutter, bar));
+        text.push_str(&format!("{} {} {}\n", paint("1;34", &number), bar, line));
+        text.push_str(&format!("{} {} {}{}", gutter, bar, " ".repeat(column),
+            paint("1;31", &carets)));
+        if let Some(help) = self.help() {
+            text.push(' ');
+            text.push_str(&paint("1;31", help));
+        }
+        text.push('\n');
+        text
+    }
+}
+
+/// How the diagnostics are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Plain,
+    /// With the ANSI escape codes of the colors, for the terminals
+    Ansi,
+}
+
+/// Renders all the `errors` of the `source` of the `file` as rustc-style
+/// diagnostics, separated by an empty line
+pub fn render_diagnostics(errors: &[ParserError], file: &str, source: &str,
+    mode: ColorMode) -> String {
+    errors.iter()
+        .map(|error| error.render(file, source, mode))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.span().start, self.message())
+    }
+}
+
+impl Error for ParserError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::Position;
+
+    #[test]
+    fn render_tests() {
+        let source = "# Hi\n\t```rust\nfn main() {}\n";
+        let error = ParserError::UnclosedCodeFence(Span::new(
+            Position { offset: 6, line: 1, column: 1 },
+            Position { offset: 9, line: 1, column: 4 },
+        ));
+        assert_eq!(error.to_string(), "2:2: unclosed code fence");
+        assert_eq!(error.render("post.md", source, ColorMode::Plain), "\
+error: unclosed code fence
+ --> post.md:2:2
+  |
+2 |  ```rust
+  |  ^^^ opened here, it lasts until the end
+");
+
+        // The colors only change the style
+        let ansi = error.render("post.md", source, ColorMode::Ansi);
+        assert!(ansi.starts_with("\x1b[1;31merror\x1b[0m\x1b[1m: unclosed"));
+        let plain: String = ansi.split("\x1b[").enumerate()
+            .map(|(i, part)| if i == 0 { part } else { &part[part.find('m').unwrap() + 1..] })
+            .collect();
+        assert_eq!(plain, error.render("post.md", source, ColorMode::Plain));
+
+        // The char without a line break after it and the line numbers wider
+        // than the gutter
+        let source = format!("{}a\0", "\n".repeat(9));
+        let error = ParserError::UnexpectedChar(Span::new(
+            Position { offset: 10, line: 9, column: 1 },
+            Position { offset: 11, line: 9, column: 2 },
+        ), '\0');
+        assert_eq!(render_diagnostics(&[error.clone(), error], "-", &source, ColorMode::Plain),
+            "error: unexpected char '\\0'\n  --> -:10:2\n   |\n10 | a\0\n   |  ^ \
+            this char is replaced with U+FFFD in the html\n\n\
+            error: unexpected char '\\0'\n  --> -:10:2\n   |\n10 | a\0\n   |  ^ \
+            this char is replaced with U+FFFD in the html\n");
+    }
+}
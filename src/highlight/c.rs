@@ -0,0 +1,147 @@
+use super::{is_ident_start, Class, Scanner};
+
+const KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "const", "continue", "default", "do", "else", "enum",
+    "extern", "for", "goto", "if", "inline", "register", "restrict", "return",
+    "sizeof", "static", "struct", "switch", "typedef", "union", "volatile", "while",
+    "_Alignas", "_Alignof", "_Atomic", "_Generic", "_Noreturn", "_Static_assert",
+    "_Thread_local", "true", "false", "NULL",
+];
+
+const CPP_KEYWORDS: &[&str] = &[
+    "alignas", "alignof", "asm", "catch", "class", "concept", "consteval",
+    "constexpr", "constinit", "const_cast", "co_await", "co_return", "co_yield",
+    "decltype", "delete", "dynamic_cast", "explicit", "export", "friend", "mutable",
+    "namespace", "new", "noexcept", "nullptr", "operator", "override", "final",
+    "private", "protected", "public", "reinterpret_cast", "requires",
+    "static_assert", "static_cast", "template", "this", "throw", "try", "typeid",
+    "typename", "using", "virtual",
+];
+
+const TYPES: &[&str] = &[
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned",
+    "bool", "_Bool", "_Complex", "wchar_t", "char8_t", "char16_t", "char32_t",
+];
+
+/// Highlights C code, or C++ code if `cpp`
+pub(super) fn highlight(code: &str, cpp: bool) -> Vec<(Option<&'static str>, String)> {
+    let mut s = Scanner::new(code);
+    while let Some(ch) = s.peek(0) {
+        let start = s.pos;
+        let class = match ch {
+            '#' if s.at_line_start() => {
+                eat_directive(&mut s);
+                Some(Class::Preprocessor)
+            }
+            '/' if s.peek(1) == Some('/') => {
+                s.eat_line();
+                Some(Class::Comment)
+            }
+            '/' if s.peek(1) == Some('*') => {
+                s.pos += 2;
+                s.eat_block_comment(false);
+                Some(Class::Comment)
+            }
+            '"' | '\'' => {
+                s.pos += 1;
+                s.eat_string(ch, false);
+                Some(if ch == '"' { Class::String } else { Class::Char })
+            }
+            _ if ch.is_ascii_digit() => {
+                s.eat_number();
+                Some(Class::Number)
+            }
+            _ if is_ident_start(ch) => ident(&mut s, cpp),
+            _ => {
+                s.pos += 1;
+                None
+            }
+        };
+        s.emit(start, class);
+    }
+    s.pieces
+}
+
+/// Eats a preprocessor directive until the end of its line, the lines ending
+/// with `\` are continued by the next one
+fn eat_directive(s: &mut Scanner) {
+    loop {
+        s.eat_line();
+        if s.pos > 0 && s.chars[s.pos - 1] == '\\' && s.peek(0) == Some('\n') {
+            s.pos += 1;
+        } else {
+            break;
+        }
+    }
+}
+
+/// An identifier, a keyword, a type or the prefix of a C++ raw string
+fn ident(s: &mut Scanner, cpp: bool) -> Option<Class> {
+    let start = s.pos;
+    s.eat_ident();
+    let word: String = s.chars[start..s.pos].iter().collect();
+
+    if cpp && word.ends_with('R') && s.peek(0) == Some('"')
+        && matches!(word.as_str(), "R" | "LR" | "uR" | "UR" | "u8R") {
+        eat_raw_string(s);
+        return Some(Class::String);
+    }
+
+    if KEYWORDS.contains(&word.as_str()) || (cpp && CPP_KEYWORDS.contains(&word.as_str())) {
+        Some(Class::Keyword)
+    } else if TYPES.contains(&word.as_str()) || word.ends_with("_t") {
+        Some(Class::Type)
+    } else {
+        None
+    }
+}
+
+/// Eats a `R"delim(raw string)delim"` after its `R`
+fn eat_raw_string(s: &mut Scanner) {
+    s.pos += 1;
+    let start = s.pos;
+    s.eat_while(|ch| ch != '(' && ch != '"' && ch != '\n');
+    let mut end = vec![')'];
+    end.extend_from_slice(&s.chars[start..s.pos]);
+    end.push('"');
+
+    while s.peek(0).is_some() {
+        if s.chars[s.pos..].starts_with(&end) {
+            s.pos += end.len();
+            return;
+        }
+        s.pos += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::to_html;
+
+    fn html(code: &str, cpp: bool) -> String {
+        to_html(&highlight(code, cpp))
+    }
+
+    #[test]
+    fn c_tests() {
+        assert_eq!(html("#include <stdio.h>\nint main() { return 0; }", false),
+            "<span class=\"pp\">#include &lt;stdio.h&gt;</span>\n\
+            <span class=\"ty\">int</span> main() { <span class=\"kw\">return</span> \
+            <span class=\"num\">0</span>; }");
+        assert_eq!(html("  #define A(x) \\\n  x\nsize_t n = 'a';", false),
+            "  <span class=\"pp\">#define A(x) \\\n  x</span>\n\
+            <span class=\"ty\">size_t</span> n = <span class=\"ch\">&#39;a&#39;</span>;");
+
+        // C++ keywords are only keywords in C++
+        assert_eq!(html("class A;", false), "class A;");
+        assert_eq!(html("class A;", true), "<span class=\"kw\">class</span> A;");
+        assert_eq!(html("R\"x()\")x\" y", true),
+            "<span class=\"str\">R&quot;x()&quot;)x&quot;</span> y");
+
+        // The comments don't nest and the strings end with their line
+        assert_eq!(html("/* /* */ a", false), "<span class=\"com\">/* /* */</span> a");
+        assert_eq!(html("\"open\nint", false),
+            "<span class=\"str\">&quot;open</span>\n<span class=\"ty\">int</span>");
+    }
+}
@@ -0,0 +1,294 @@
+//! Syntax highlighting of the code blocks, the code is split in tokens by a
+//! lexer of its language and each token is put in a `<span class="...">`
+//! that can be styled with css
+
+mod rust;
+mod c;
+
+use std::fmt;
+
+use super::html::EscapeHtml;
+
+/// A lexer of a language used to highlight the code blocks
+pub trait Highlighter {
+    /// Splits the `code` into pieces, each one with the css class of its
+    /// kind or `None` if it's just text. The pieces joined must be the `code`
+    fn highlight(&self, code: &str) -> Vec<(Option<&'static str>, String)>;
+}
+
+struct Rust;
+struct C;
+struct Cpp;
+
+impl Highlighter for Rust {
+    fn highlight(&self, code: &str) -> Vec<(Option<&'static str>, String)> {
+        rust::highlight(code)
+    }
+}
+
+impl Highlighter for C {
+    fn highlight(&self, code: &str) -> Vec<(Option<&'static str>, String)> {
+        c::highlight(code, false)
+    }
+}
+
+impl Highlighter for Cpp {
+    fn highlight(&self, code: &str) -> Vec<(Option<&'static str>, String)> {
+        c::highlight(code, true)
+    }
+}
+
+/// The highlighters by the names of their languages, the ones used in the
+/// info string of the code blocks. The default one has Rust, C and C++
+pub struct Highlighters {
+    /// The names of each language, the first one is its css class, and its
+    /// highlighter
+    languages: Vec<(Vec<String>, Box<dyn Highlighter>)>,
+}
+
+impl Highlighters {
+    /// Instantiate a registry without any highlighter
+    pub fn new() -> Self {
+        Highlighters { languages: Vec::new() }
+    }
+
+    /// Registers the `highlighter` of a language by its `names`, the first
+    /// one is the name of the language and the rest are aliases, the names
+    /// are case-insensitive. It replaces the previous highlighter of any of
+    /// the names
+    pub fn register<H>(&mut self, names: &[&str], highlighter: H)
+    where
+        H: Highlighter + 'static,
+    {
+        let names = names.iter().map(|name| name.to_lowercase()).collect();
+        self.languages.push((names, Box::new(highlighter)));
+    }
+
+    /// The name of the language of the info string `name`, it can be an
+    /// alias, with its highlighter
+    pub fn get(&self, name: &str) -> Option<(&str, &dyn Highlighter)> {
+        let name = name.to_lowercase();
+        self.languages.iter().rev()
+            .find(|(names, _)| names.contains(&name))
+            .map(|(names, highlighter)| (names[0].as_str(), highlighter.as_ref()))
+    }
+}
+
+impl Default for Highlighters {
+    fn default() -> Self {
+        let mut highlighters = Highlighters::new();
+        highlighters.register(&["rust", "rs"], Rust);
+        highlighters.register(&["c", "h"], C);
+        highlighters.register(&["cpp", "c++", "hpp", "cc", "cxx"], Cpp);
+        highlighters
+    }
+}
+
+/// The html of the highlighted `pieces` as a string, for the tests
+#[cfg(test)]
+pub(crate) fn to_html(pieces: &[(Option<&'static str>, String)]) -> String {
+    let mut html = String::new();
+    let _ = write_html(&mut html, pieces);
+    html
+}
+
+/// Writes the html of the highlighted `pieces` into `out`, each one in a
+/// span of its class
+pub(crate) fn write_html<W: fmt::Write + ?Sized>(out: &mut W,
+    pieces: &[(Option<&'static str>, String)]) -> fmt::Result {
+    for (class, text) in pieces {
+        match class {
+            Some(class) => write!(out, "<span class=\"{}\">{}</span>", class, EscapeHtml(text))?,
+            None => write!(out, "{}", EscapeHtml(text))?,
+        }
+    }
+    Ok(())
+}
+
+/// The kind of a highlighted token, its name is the css class of the span
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Class {
+    Keyword,
+    Type,
+    String,
+    Char,
+    Number,
+    Comment,
+    /// `'a` in Rust
+    Lifetime,
+    /// `name!` in Rust
+    Macro,
+    /// `#[attribute]` in Rust
+    Attribute,
+    /// `#include` and the other directives in C and C++
+    Preprocessor,
+}
+
+impl Class {
+    fn name(self) -> &'static str {
+        match self {
+            Class::Keyword => "kw",
+            Class::Type => "ty",
+            Class::String => "str",
+            Class::Char => "ch",
+            Class::Number => "num",
+            Class::Comment => "com",
+            Class::Lifetime => "lt",
+            Class::Macro => "mac",
+            Class::Attribute => "attr",
+            Class::Preprocessor => "pp",
+        }
+    }
+}
+
+/// Cursor over the chars of the code used by the lexers, the tokens are
+/// added to the pieces as they are found
+struct Scanner {
+    chars: Vec<char>,
+    pos: usize,
+    pieces: Vec<(Option<&'static str>, String)>,
+}
+
+impl Scanner {
+    fn new(code: &str) -> Self {
+        Scanner {
+            chars: code.chars().collect(),
+            pos: 0,
+            pieces: Vec::new(),
+        }
+    }
+
+    /// The char `n` positions ahead
+    fn peek(&self, n: usize) -> Option<char> {
+        self.chars.get(self.pos + n).copied()
+    }
+
+    /// Adds the chars from `start` until the current position as a token of
+    /// the `class`, without class they are joined with the text before
+    fn emit(&mut self, start: usize, class: Option<Class>) {
+        let text = self.chars[start..self.pos].iter();
+        match (self.pieces.last_mut(), class) {
+            (Some((None, prev)), None) => prev.extend(text),
+            _ => self.pieces.push((class.map(Class::name), text.collect())),
+        }
+    }
+
+    fn eat_while(&mut self, f: impl Fn(char) -> bool) {
+        while self.peek(0).is_some_and(&f) {
+            self.pos += 1;
+        }
+    }
+
+    /// Eats until the end of the line, without the `\n`
+    fn eat_line(&mut self) {
+        self.eat_while(|ch| ch != '\n');
+    }
+
+    fn eat_ident(&mut self) {
+        self.eat_while(is_ident_continue);
+    }
+
+    /// Eats a string or a char after its opening `quote` until the closing
+    /// one, if it's not `multiline` it also ends at the end of the line, so
+    /// one without end doesn't take the whole code
+    fn eat_string(&mut self, quote: char, multiline: bool) {
+        while let Some(ch) = self.peek(0) {
+            if ch == '\n' && !multiline {
+                return;
+            }
+            self.pos += 1;
+            if ch == '\\' && self.peek(0).is_some() {
+                self.pos += 1;
+            } else if ch == quote {
+                return;
+            }
+        }
+    }
+
+    /// Eats a block comment after its `/*` until its `*/` or the end of the
+    /// code, the `nested` comments have to be closed too
+    fn eat_block_comment(&mut self, nested: bool) {
+        let mut depth = 1;
+        while let Some(ch) = self.peek(0) {
+            if ch == '*' && self.peek(1) == Some('/') {
+                self.pos += 2;
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            } else if nested && ch == '/' && self.peek(1) == Some('*') {
+                self.pos += 2;
+                depth += 1;
+            } else {
+                self.pos += 1;
+            }
+        }
+    }
+
+    /// Eats a number literal, with its suffix, a fraction and an exponent
+    fn eat_number(&mut self) {
+        let start = self.pos;
+        let hex = self.peek(0) == Some('0') && matches!(self.peek(1), Some('x') | Some('X'));
+        while let Some(ch) = self.peek(0) {
+            let fraction = ch == '.' && self.peek(1).is_some_and(|c| c.is_ascii_digit());
+            let exponent = !hex && matches!(ch, '+' | '-')
+                && matches!(self.chars[self.pos - 1], 'e' | 'E')
+                && self.chars[start..self.pos - 1].iter()
+                    .all(|c| c.is_ascii_digit() || *c == '.' || *c == '_');
+            if is_ident_continue(ch) || fraction || exponent {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// If there are only spaces between the start of the line and the
+    /// current position
+    fn at_line_start(&self) -> bool {
+        self.chars[..self.pos].iter().rev()
+            .take_while(|ch| **ch != '\n')
+            .all(|ch| *ch == ' ' || *ch == '\t')
+    }
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch == '_' || ch.is_alphabetic()
+}
+
+fn is_ident_continue(ch: char) -> bool {
+    ch == '_' || ch.is_alphanumeric()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Highlights the whole code as a string
+    struct Quoted;
+
+    impl Highlighter for Quoted {
+        fn highlight(&self, code: &str) -> Vec<(Option<&'static str>, String)> {
+            vec![(Some("str"), code.to_owned())]
+        }
+    }
+
+    #[test]
+    fn registry_tests() {
+        let mut highlighters = Highlighters::default();
+        assert_eq!(highlighters.get("RS").map(|(name, _)| name), Some("rust"));
+        assert_eq!(highlighters.get("c++").map(|(name, _)| name), Some("cpp"));
+        assert!(highlighters.get("toml").is_none());
+
+        // The new highlighters replace the old ones of their names
+        highlighters.register(&["toml", "c"], Quoted);
+        let (name, toml) = highlighters.get("toml").unwrap();
+        assert_eq!(name, "toml");
+        assert_eq!(to_html(&toml.highlight("a = <1>")),
+            "<span class=\"str\">a = &lt;1&gt;</span>");
+        assert_eq!(highlighters.get("c").map(|(name, _)| name), Some("toml"));
+        assert_eq!(highlighters.get("h").map(|(name, _)| name), Some("c"));
+
+        assert!(Highlighters::new().get("rust").is_none());
+    }
+}
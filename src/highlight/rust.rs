@@ -0,0 +1,185 @@
+use super::{is_ident_start, Class, Scanner};
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else",
+    "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop",
+    "match", "mod", "move", "mut", "pub", "ref", "return", "self", "static",
+    "struct", "super", "trait", "true", "type", "union", "unsafe", "use", "where",
+    "while", "yield",
+];
+
+const PRIMITIVES: &[&str] = &[
+    "bool", "char", "str", "i8", "i16", "i32", "i64", "i128", "isize", "u8",
+    "u16", "u32", "u64", "u128", "usize", "f32", "f64",
+];
+
+/// Highlights Rust code
+pub(super) fn highlight(code: &str) -> Vec<(Option<&'static str>, String)> {
+    let mut s = Scanner::new(code);
+    while let Some(ch) = s.peek(0) {
+        let start = s.pos;
+        let class = match ch {
+            '/' if s.peek(1) == Some('/') => {
+                s.eat_line();
+                Some(Class::Comment)
+            }
+            '/' if s.peek(1) == Some('*') => {
+                s.pos += 2;
+                s.eat_block_comment(true);
+                Some(Class::Comment)
+            }
+            '"' => {
+                s.pos += 1;
+                s.eat_string('"', true);
+                Some(Class::String)
+            }
+            '\'' => char_or_lifetime(&mut s),
+            '#' if s.peek(1) == Some('[')
+                || (s.peek(1) == Some('!') && s.peek(2) == Some('[')) => {
+                eat_attribute(&mut s);
+                Some(Class::Attribute)
+            }
+            _ if ch.is_ascii_digit() => {
+                s.eat_number();
+                Some(Class::Number)
+            }
+            _ if is_ident_start(ch) => ident(&mut s),
+            _ => {
+                s.pos += 1;
+                None
+            }
+        };
+        s.emit(start, class);
+    }
+    s.pieces
+}
+
+/// A `'c'` char or a `'a` lifetime, or a label
+fn char_or_lifetime(s: &mut Scanner) -> Option<Class> {
+    match (s.peek(1), s.peek(2)) {
+        (Some('\\'), _) => {
+            s.pos += 1;
+            s.eat_string('\'', false);
+            Some(Class::Char)
+        }
+        (Some(ch), Some('\'')) if ch != '\n' => {
+            s.pos += 3;
+            Some(Class::Char)
+        }
+        (Some(ch), _) if is_ident_start(ch) => {
+            s.pos += 1;
+            s.eat_ident();
+            Some(Class::Lifetime)
+        }
+        _ => {
+            s.pos += 1;
+            None
+        }
+    }
+}
+
+/// Eats an `#[attribute]` or `#![attribute]` until its `]`
+fn eat_attribute(s: &mut Scanner) {
+    s.pos += if s.peek(1) == Some('!') { 2 } else { 1 };
+    let mut depth = 0;
+    while let Some(ch) = s.peek(0) {
+        s.pos += 1;
+        match ch {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+            '"' => s.eat_string('"', true),
+            _ => {}
+        }
+    }
+}
+
+/// An identifier, a keyword, a type, a macro or the prefix of a byte or raw
+/// string
+fn ident(s: &mut Scanner) -> Option<Class> {
+    let start = s.pos;
+    s.eat_ident();
+    let word: String = s.chars[start..s.pos].iter().collect();
+
+    match (word.as_str(), s.peek(0)) {
+        ("b", Some(quote)) if quote == '"' || quote == '\'' => {
+            s.pos += 1;
+            s.eat_string(quote, quote == '"');
+            return Some(if quote == '"' { Class::String } else { Class::Char });
+        }
+        ("r", Some('"')) | ("r", Some('#')) | ("br", Some('"')) | ("br", Some('#'))
+            if eat_raw_string(s) => {
+            return Some(Class::String);
+        }
+        (_, Some('!')) if s.peek(1) != Some('=') => {
+            s.pos += 1;
+            return Some(Class::Macro);
+        }
+        _ => {}
+    }
+
+    if KEYWORDS.contains(&word.as_str()) {
+        Some(Class::Keyword)
+    } else if PRIMITIVES.contains(&word.as_str()) || word.starts_with(char::is_uppercase) {
+        Some(Class::Type)
+    } else {
+        None
+    }
+}
+
+/// Eats a `r#"raw string"#` after its `r`, it doesn't eat anything if there
+/// is no string after the `#`s
+fn eat_raw_string(s: &mut Scanner) -> bool {
+    let hashes = s.chars[s.pos..].iter().take_while(|ch| **ch == '#').count();
+    if s.peek(hashes) != Some('"') {
+        return false;
+    }
+    s.pos += hashes + 1;
+    while let Some(ch) = s.peek(0) {
+        s.pos += 1;
+        if ch == '"' && (0..hashes).all(|i| s.peek(i) == Some('#')) {
+            s.pos += hashes;
+            break;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::to_html;
+
+    fn html(code: &str) -> String {
+        to_html(&highlight(code))
+    }
+
+    #[test]
+    fn rust_tests() {
+        assert_eq!(html("fn main() {}"),
+            "<span class=\"kw\">fn</span> main() {}");
+        assert_eq!(html("let v: Vec<u8> = vec![1, 0x1F];"),
+            "<span class=\"kw\">let</span> v: <span class=\"ty\">Vec</span>&lt;\
+            <span class=\"ty\">u8</span>&gt; = <span class=\"mac\">vec!</span>[\
+            <span class=\"num\">1</span>, <span class=\"num\">0x1F</span>];");
+        assert_eq!(html("#[derive(Debug)]\nstruct A<'a>(&'a str, char);"),
+            "<span class=\"attr\">#[derive(Debug)]</span>\n\
+            <span class=\"kw\">struct</span> <span class=\"ty\">A</span>&lt;\
+            <span class=\"lt\">&#39;a</span>&gt;(&amp;<span class=\"lt\">&#39;a</span> \
+            <span class=\"ty\">str</span>, <span class=\"ty\">char</span>);");
+        assert_eq!(html("'a' '\\'' b\"x\" r#\"a\"b\"# 1.5e-3"),
+            "<span class=\"ch\">&#39;a&#39;</span> <span class=\"ch\">&#39;\\&#39;&#39;</span> \
+            <span class=\"str\">b&quot;x&quot;</span> <span class=\"str\">r#&quot;a&quot;b&quot;#</span> \
+            <span class=\"num\">1.5e-3</span>");
+
+        // The nested comments and a string without end
+        assert_eq!(html("/* a /* b */ c */ x // d"),
+            "<span class=\"com\">/* a /* b */ c */</span> x <span class=\"com\">// d</span>");
+        assert_eq!(html("\"open\nfn"), "<span class=\"str\">&quot;open\nfn</span>");
+        assert_eq!(html("/* /* */ fn"), "<span class=\"com\">/* /* */ fn</span>");
+    }
+}
@@ -0,0 +1,270 @@
+//! The html of each node, the defaults of the hooks of `Renderer`. A hook
+//! that only changes part of the html can call its default from here
+
+use std::fmt::{self, Write};
+
+use super::ast::{Alignment, Block, BlockKind, Inline, TableCell};
+use super::compiler::{HtmlOptions, TableAlignment};
+use super::highlight::write_html;
+use super::render::{RenderContext, Renderer};
+
+/// A value that is written escaped to be put as text in the html or inside
+/// a quoted html attribute, so it can't be taken as markup
+pub struct EscapeHtml<'a>(pub &'a str);
+
+impl fmt::Display for EscapeHtml<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The text between the chars to escape is written at once
+        let mut start = 0;
+        for (i, ch) in self.0.char_indices() {
+            let escaped = match ch {
+                '&' => "&amp;",
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '"' => "&quot;",
+                '\'' => "&#39;",
+                _ => continue,
+            };
+            f.write_str(&self.0[start..i])?;
+            f.write_str(escaped)?;
+            start = i + 1;
+        }
+        f.write_str(&self.0[start..])
+    }
+}
+
+/// An url that is written escaped to be put inside a quoted html attribute,
+/// the chars that can't be in an url are percent-encoded
+pub struct EscapeUrl<'a>(pub &'a str);
+
+impl fmt::Display for EscapeUrl<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ch in self.0.chars() {
+            match ch {
+                '&' => f.write_str("&amp;")?,
+                '\'' => f.write_str("&#39;")?,
+                _ if ch.is_ascii_alphanumeric() || "-._~:/?#[]@!$()*+,;=%".contains(ch) => {
+                    f.write_char(ch)?;
+                }
+                _ => {
+                    let mut bytes = [0; 4];
+                    for b in ch.encode_utf8(&mut bytes).bytes() {
+                        write!(f, "%{:02X}", b)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the ` title="..."` attribute of links and images if there is one
+fn write_title(ctx: &mut RenderContext, title: Option<&str>) -> fmt::Result {
+    match title {
+        Some(title) => write!(ctx, " title=\"{}\"", EscapeHtml(title)),
+        None => Ok(()),
+    }
+}
+
+/// Writes the alignment attribute of a table cell, if it has one
+fn write_alignment(ctx: &mut RenderContext, alignment: Alignment, options: &HtmlOptions)
+    -> fmt::Result {
+    let name = match alignment {
+        Alignment::None => return Ok(()),
+        Alignment::Left => "left",
+        Alignment::Center => "center",
+        Alignment::Right => "right",
+    };
+    match &options.table_alignment {
+        TableAlignment::Style => write!(ctx, " style=\"text-align: {}\"", name),
+        TableAlignment::Class(prefix) => write!(ctx, " class=\"{}{}\"", EscapeHtml(prefix), name),
+    }
+}
+
+/// Each block in its own line
+pub fn document<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    blocks: &[Block]) -> fmt::Result {
+    for block in blocks {
+        renderer.block(ctx, block)?;
+        ctx.write_char('\n')?;
+    }
+    Ok(())
+}
+
+/// `<h1 id="...">`, with a link to itself after the text if the options have
+/// an anchor
+pub fn heading<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext, level: usize,
+    content: &[Inline], id: Option<&str>) -> fmt::Result {
+    match id {
+        Some(id) => write!(ctx, "<h{} id=\"{}\">", level, EscapeHtml(id))?,
+        None => write!(ctx, "<h{}>", level)?,
+    }
+    renderer.inlines(ctx, content)?;
+    if let (Some(id), Some(anchor)) = (id, &ctx.options().heading_anchor) {
+        write!(ctx, " <a class=\"anchor\" href=\"#{}\">{}</a>", EscapeUrl(id), EscapeHtml(anchor))?;
+    }
+    write!(ctx, "</h{}>", level)
+}
+
+pub fn paragraph<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    content: &[Inline]) -> fmt::Result {
+    ctx.write_str("<p>")?;
+    renderer.inlines(ctx, content)?;
+    ctx.write_str("</p>")
+}
+
+/// `<pre><code>` highlighted if there is a highlighter of its language, the
+/// known languages have their name instead of the alias
+pub fn code_block<R: Renderer + ?Sized>(_: &mut R, ctx: &mut RenderContext, lang: Option<&str>,
+    code: &str) -> fmt::Result {
+    let highlighter = lang.and_then(|l| ctx.highlighters().get(l));
+    ctx.write_str("<pre><code")?;
+    if let Some(lang) = highlighter.map(|(name, _)| name).or(lang) {
+        write!(ctx, " class=\"language-{}\"", EscapeHtml(lang))?;
+    }
+    ctx.write_char('>')?;
+    match highlighter {
+        Some((_, highlighter)) => write_html(ctx, &highlighter.highlight(code))?,
+        None => write!(ctx, "{}", EscapeHtml(code))?,
+    }
+    ctx.write_str("</code></pre>")
+}
+
+/// `<ul>` or `<ol>`, with its `start` if it isn't 1
+pub fn list<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    start: Option<usize>, tight: bool, items: &[Vec<Block>]) -> fmt::Result {
+    match start {
+        None => ctx.write_str("<ul>\n")?,
+        Some(1) => ctx.write_str("<ol>\n")?,
+        Some(start) => writeln!(ctx, "<ol start=\"{}\">", start)?,
+    }
+    for item in items {
+        renderer.list_item(ctx, item, tight)?;
+    }
+    ctx.write_str(if start.is_some() { "</ol>" } else { "</ul>" })
+}
+
+/// The `<li>` of a list item, in a tight list the paragraphs are just their
+/// text and the other blocks are in their own line
+pub fn list_item<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    blocks: &[Block], tight: bool) -> fmt::Result {
+    ctx.write_str("<li>")?;
+    // If the last thing written is a block, so the next one is already in
+    // its own line
+    let mut after_block = false;
+    for block in blocks {
+        match &block.kind {
+            BlockKind::Paragraph(content) if tight => {
+                renderer.inlines(ctx, content)?;
+                after_block &= content.is_empty();
+            }
+            _ => {
+                if !after_block {
+                    ctx.write_char('\n')?;
+                }
+                renderer.block(ctx, block)?;
+                ctx.write_char('\n')?;
+                after_block = true;
+            }
+        }
+    }
+    ctx.write_str("</li>\n")
+}
+
+pub fn quote<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext, blocks: &[Block])
+    -> fmt::Result {
+    ctx.write_str("<blockquote>\n")?;
+    for block in blocks {
+        renderer.block(ctx, block)?;
+        ctx.write_char('\n')?;
+    }
+    ctx.write_str("</blockquote>")
+}
+
+/// `<table>` with a `<thead>` and a `<tbody>` if it has rows
+pub fn table<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    alignments: &[Alignment], header: &[TableCell], rows: &[Vec<TableCell>]) -> fmt::Result {
+    ctx.write_str("<table>\n<thead>\n")?;
+    renderer.table_row(ctx, header, alignments, true)?;
+    ctx.write_str("</thead>\n")?;
+    if !rows.is_empty() {
+        ctx.write_str("<tbody>\n")?;
+        for row in rows {
+            renderer.table_row(ctx, row, alignments, false)?;
+        }
+        ctx.write_str("</tbody>\n")?;
+    }
+    ctx.write_str("</table>")
+}
+
+/// `<tr>` with a `th` or `td` cell for each column, aligned as the options
+/// say
+pub fn table_row<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    cells: &[TableCell], alignments: &[Alignment], header: bool) -> fmt::Result {
+    let tag = if header { "th" } else { "td" };
+    ctx.write_str("<tr>\n")?;
+    for (cell, alignment) in cells.iter().zip(alignments) {
+        write!(ctx, "<{}", tag)?;
+        write_alignment(ctx, *alignment, ctx.options())?;
+        ctx.write_char('>')?;
+        renderer.inlines(ctx, &cell.content)?;
+        writeln!(ctx, "</{}>", tag)?;
+    }
+    ctx.write_str("</tr>\n")
+}
+
+pub fn thematic_break<R: Renderer + ?Sized>(_: &mut R, ctx: &mut RenderContext) -> fmt::Result {
+    ctx.write_str("<hr>")
+}
+
+/// The `<nav>` of the outline, see `render_toc`
+pub fn table_of_contents<R: Renderer + ?Sized>(_: &mut R, ctx: &mut RenderContext)
+    -> fmt::Result {
+    ctx.write_toc()
+}
+
+pub fn text<R: Renderer + ?Sized>(_: &mut R, ctx: &mut RenderContext, text: &str) -> fmt::Result {
+    write!(ctx, "{}", EscapeHtml(text))
+}
+
+pub fn code<R: Renderer + ?Sized>(_: &mut R, ctx: &mut RenderContext, code: &str) -> fmt::Result {
+    write!(ctx, "<code>{}</code>", EscapeHtml(code))
+}
+
+pub fn emphasis<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    children: &[Inline]) -> fmt::Result {
+    ctx.write_str("<em>")?;
+    renderer.inlines(ctx, children)?;
+    ctx.write_str("</em>")
+}
+
+pub fn strong<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    children: &[Inline]) -> fmt::Result {
+    ctx.write_str("<strong>")?;
+    renderer.inlines(ctx, children)?;
+    ctx.write_str("</strong>")
+}
+
+pub fn link<R: Renderer + ?Sized>(renderer: &mut R, ctx: &mut RenderContext,
+    children: &[Inline], url: &str, title: Option<&str>) -> fmt::Result {
+    write!(ctx, "<a href=\"{}\"", EscapeUrl(url))?;
+    write_title(ctx, title)?;
+    ctx.write_char('>')?;
+    renderer.inlines(ctx, children)?;
+    ctx.write_str("</a>")
+}
+
+pub fn image<R: Renderer + ?Sized>(_: &mut R, ctx: &mut RenderContext, alt: &str, src: &str,
+    title: Option<&str>) -> fmt::Result {
+    write!(ctx, "<img src=\"{}\" alt=\"{}\"", EscapeUrl(src), EscapeHtml(alt))?;
+    write_title(ctx, title)?;
+    ctx.write_char('>')
+}
+
+pub fn soft_break<R: Renderer + ?Sized>(_: &mut R, ctx: &mut RenderContext) -> fmt::Result {
+    ctx.write_char('\n')
+}
+
+pub fn hard_break<R: Renderer + ?Sized>(_: &mut R, ctx: &mut RenderContext) -> fmt::Result {
+    ctx.write_str("<br>\n")
+}
@@ -2,23 +2,273 @@ mod parser;
 mod tokenizer;
 mod compiler;
 mod errors;
+mod highlight;
+mod slug;
+mod toc;
+mod render;
+mod text;
+mod ansi;
+pub mod html;
+pub mod ast;
+pub mod span;
 
-/// Function that given an `source` &str compiles it from Markdown to Html
+pub use ast::{Block, BlockKind, Document, Inline, InlineKind};
+pub use span::{Position, Span};
+pub use errors::{render_diagnostics, ColorMode, ParserError};
+pub use tokenizer::Token;
+pub use highlight::{Highlighter, Highlighters};
+pub use compiler::{
+    render_to_fmt, render_to_fmt_with_options, render_to_fmt_with_renderer, render_to_io,
+    render_to_io_with_options, render_to_io_with_renderer, HtmlOptions, TableAlignment,
+};
+pub use render::{HtmlRenderer, RenderContext, Renderer};
+pub use text::{render_text, TextOptions, TextRenderer};
+pub use ansi::{render_ansi, AnsiOptions, AnsiRenderer, CodeStyle};
+pub use slug::{slugify, Slugger};
+pub use toc::{render_toc, TocEntry, TocOptions};
+
+/// Parses the `source` returning the document with the errors found, the
+/// malformed markdown ends up as text in the document
+/// `Tokenize -> Parse`
+fn parse(source: &str) -> (Document<'_>, Vec<ParserError>) {
+    // Tokenize and parse it
+    let mut blocks = parse_markdown_blocks(source);
+    let document = Document { blocks: blocks.by_ref().collect() };
+
+    (document, blocks.errors().to_vec())
+}
+
+/// Iterator over the blocks of a markdown source, they are parsed only when
+/// they are needed and without keeping the tokens of the source, see
+/// `parse_markdown_blocks`
+pub struct Blocks<'a> {
+    parser: parser::MDParser<'a, tokenizer::TokenizerIterator<'a>>,
+}
+
+impl Blocks<'_> {
+    /// The errors found until now, the ones of the tokenizer are found at
+    /// the start
+    pub fn errors(&self) -> &[ParserError] {
+        self.parser.errors()
+    }
+}
+
+impl<'a> Iterator for Blocks<'a> {
+    type Item = Block<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parser.next()
+    }
+}
+
+/// Parses the `source` block by block as they are taken, so the memory used
+/// doesn't grow with the source, like `parse_markdown` it never fails.
+/// The link definitions can be after their links, so the whole source is
+/// tokenized once before the first block to find them, and again as the
+/// blocks are parsed. That first pass takes about a third of the time
+pub fn parse_markdown_blocks(source: &str) -> Blocks<'_> {
+    Blocks { parser: parser::MDParser::new(source) }
+}
+
+/// Compiles the `source` returning the html with the errors found
 /// `Tokenize -> Parse -> Compile`
+fn compile(source: &str, highlighters: &Highlighters, options: &HtmlOptions)
+    -> (String, Vec<ParserError>) {
+    let (document, errors) = parse(source);
+    // Compile it, writing into a `String` can't fail
+    let mut html = String::with_capacity(source.len());
+    let _ = render_to_fmt_with_options(&document, &mut html, highlighters, options);
+    (html, errors)
+}
+
+/// Function that given an `source` &str parses it into its `Document`, it
+/// never fails, the malformed markdown is parsed as text. The text of the
+/// document is borrowed from the `source`, see `Document::into_owned`
+pub fn parse_markdown(source: &str) -> Document<'_> {
+    parse(source).0
+}
+
+/// Like `parse_markdown` but also giving the errors found, that can be shown
+/// with `render_diagnostics`
+pub fn parse_markdown_with_errors(source: &str) -> (Document<'_>, Vec<ParserError>) {
+    parse(source)
+}
+
+/// Like `parse_markdown` but if the `source` has any error the errors are
+/// returned instead of the document
+pub fn try_parse_markdown(source: &str) -> Result<Document<'_>, Vec<ParserError>> {
+    let (document, errors) = parse(source);
+    if errors.is_empty() {
+        Ok(document)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Function that given an `source` &str compiles it from Markdown to Html,
+/// it never fails, the malformed markdown is compiled as text
 pub fn compile_markdown(source: &str) -> String {
-    // Tokenize it
-    let t = tokenizer::Tokenizer::new(source);
-    // Parse it
-    let mut parser = parser::MDParser::new(t); 
-    let mut values = vec![];
-    while let Some(v) = parser.next() {
-        values.push(v);
+    compile(source, &Highlighters::default(), &HtmlOptions::default()).0
+}
+
+/// Like `compile_markdown` but the code blocks are highlighted with the
+/// `highlighters` instead of the default ones
+pub fn compile_markdown_with(source: &str, highlighters: &Highlighters) -> String {
+    compile(source, highlighters, &HtmlOptions::default()).0
+}
+
+/// Like `compile_markdown_with` but the html is also generated with the
+/// `options`, like how the tables are aligned
+pub fn compile_markdown_with_options(source: &str, highlighters: &Highlighters,
+    options: &HtmlOptions) -> String {
+    compile(source, highlighters, options).0
+}
+
+/// Like `compile_markdown` but if the `source` has any error the errors are
+/// returned instead of the html
+pub fn try_compile_markdown(source: &str) -> Result<String, Vec<ParserError>> {
+    try_compile_markdown_with(source, &Highlighters::default())
+}
+
+/// Like `try_compile_markdown` but with the `highlighters` of
+/// `compile_markdown_with`
+pub fn try_compile_markdown_with(source: &str, highlighters: &Highlighters)
+    -> Result<String, Vec<ParserError>> {
+    let (html, errors) = compile(source, highlighters, &HtmlOptions::default());
+    if errors.is_empty() {
+        Ok(html)
+    } else {
+        Err(errors)
     }
-    let mut final_value = String::new();
-    // Compile it
-    for v in values {
-        final_value.push_str(&format!("{}", v));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_tests() {
+        assert_eq!(try_compile_markdown("# Hello\n*World*"),
+            Ok("<h1 id=\"hello\">Hello</h1>\n<p><em>World</em></p>\n".into()));
+
+        assert_eq!(compile_markdown("- a\n  - b\n\n2. c\n\n   d"),
+            "<ul>\n<li>a\n<ul>\n<li>b</li>\n</ul>\n</li>\n</ul>\n\
+            <ol start=\"2\">\n<li>\n<p>c</p>\n<p>d</p>\n</li>\n</ol>\n");
+
+        assert_eq!(compile_markdown("a  \nb\nc\n\nd"),
+            "<p>a<br>\nb\nc</p>\n<p>d</p>\n");
+
+        assert_eq!(compile_markdown("`a`\n```rust\nfn main() {\n\n}\n```"),
+            "<p><code>a</code></p>\n\
+            <pre><code class=\"language-rust\"><span class=\"kw\">fn</span> main() {\n\n}\n</code></pre>\n");
+
+        // The text and the code can't be taken as html
+        assert_eq!(compile_markdown("# <h1>\nif a < b && c > d\n```\nVec<String>\n```"),
+            "<h1 id=\"h1\">&lt;h1&gt;</h1>\n<p>if a &lt; b &amp;&amp; c &gt; d</p>\n\
+            <pre><code>Vec&lt;String&gt;\n</code></pre>\n");
+
+        // The aliases have the name of the language and the unknown languages
+        // keep theirs
+        assert_eq!(compile_markdown("```rs\n1\n```\n```python\n1\n```"),
+            "<pre><code class=\"language-rust\"><span class=\"num\">1</span>\n</code></pre>\n\
+            <pre><code class=\"language-python\">1\n</code></pre>\n");
+
+        assert_eq!(compile_markdown("> a\n> > b\n>\n> - c"),
+            "<blockquote>\n<p>a</p>\n<blockquote>\n<p>b</p>\n</blockquote>\n\
+            <ul>\n<li>c</li>\n</ul>\n</blockquote>\n");
+
+        assert_eq!(compile_markdown("a\n***\nb\n---"), "<p>a</p>\n<hr>\n<h2 id=\"b\">b</h2>\n");
+
+        // The tables align their columns with styles or with classes
+        let source = "| a | b |\n|:--|--:|\n| `|` \\| | 1 |";
+        assert_eq!(compile_markdown(source),
+            "<table>\n<thead>\n<tr>\n<th style=\"text-align: left\">a</th>\n\
+            <th style=\"text-align: right\">b</th>\n</tr>\n</thead>\n<tbody>\n<tr>\n\
+            <td style=\"text-align: left\"><code>|</code> |</td>\n\
+            <td style=\"text-align: right\">1</td>\n</tr>\n</tbody>\n</table>\n");
+        let options = HtmlOptions {
+            table_alignment: TableAlignment::Class("align-".into()),
+            ..HtmlOptions::default()
+        };
+        assert_eq!(compile_markdown_with_options("a | b\n--- | :-:", &Highlighters::default(),
+            &options), "<table>\n<thead>\n<tr>\n<th>a</th>\n\
+            <th class=\"align-center\">b</th>\n</tr>\n</thead>\n</table>\n");
+
+        // The ids of the headings are unique, also the ones in the lists, and
+        // the anchors link to them
+        let options = HtmlOptions {
+            heading_anchor: Some("¶".into()),
+            ..HtmlOptions::default()
+        };
+        assert_eq!(compile_markdown_with_options("# Día 1\n- ## Día 1\n\nDía 1 {#día-1-1}\n---",
+            &Highlighters::default(), &options),
+            "<h1 id=\"día-1\">Día 1 <a class=\"anchor\" href=\"#d%C3%ADa-1\">¶</a></h1>\n\
+            <ul>\n<li>\n<h2 id=\"día-1-2\">Día 1 \
+            <a class=\"anchor\" href=\"#d%C3%ADa-1-2\">¶</a></h2>\n</li>\n</ul>\n\
+            <h2 id=\"día-1-1\">Día 1 <a class=\"anchor\" href=\"#d%C3%ADa-1-1\">¶</a></h2>\n");
+        let options = HtmlOptions { heading_ids: false, ..HtmlOptions::default() };
+        assert_eq!(compile_markdown_with_options("# a\n# b {#b}", &Highlighters::default(),
+            &options), "<h1>a</h1>\n<h1 id=\"b\">b</h1>\n");
+        // Unless there is a table of contents that links to them
+        assert_eq!(compile_markdown_with_options("> [[toc]]\n\n# a\n# b {#c}",
+            &Highlighters::default(), &options), "<blockquote>\n<nav class=\"toc\">\n<ol>\n\
+            <li><a href=\"#a\">a</a></li>\n<li><a href=\"#c\">b</a></li>\n</ol>\n</nav>\n\
+            </blockquote>\n<h1 id=\"a\">a</h1>\n<h1 id=\"c\">b</h1>\n");
+
+        // The table of contents where its marker is, with the ids of the
+        // headings
+        let options = HtmlOptions {
+            toc: TocOptions { min_level: 2, max_level: 6 },
+            ..HtmlOptions::default()
+        };
+        assert_eq!(compile_markdown_with_options("# Post\n[[toc]]\n## A\n## B {#b}",
+            &Highlighters::default(), &options),
+            "<h1 id=\"post\">Post</h1>\n<nav class=\"toc\">\n<ol>\n\
+            <li><a href=\"#a\">A</a></li>\n<li><a href=\"#b\">B</a></li>\n</ol>\n</nav>\n\
+            <h2 id=\"a\">A</h2>\n<h2 id=\"b\">B</h2>\n");
+
+        // The headings without a slug still have an id to link to
+        let options = HtmlOptions { heading_anchor: Some("¶".into()), ..options };
+        assert_eq!(compile_markdown_with_options("[[toc]]\n##\n## 🎉", &Highlighters::default(),
+            &options), "<nav class=\"toc\">\n<ol>\n<li><a href=\"#section\"></a></li>\n\
+            <li><a href=\"#section-1\">🎉</a></li>\n</ol>\n</nav>\n\
+            <h2 id=\"section\"> <a class=\"anchor\" href=\"#section\">¶</a></h2>\n\
+            <h2 id=\"section-1\">🎉 <a class=\"anchor\" href=\"#section-1\">¶</a></h2>\n");
+
+        // Never panics, the errors are given with the best-effort html
+        let source = "Wrong\0**input";
+        assert_eq!(compile_markdown(source), "<p>Wrong\u{FFFD}**input</p>\n");
+        assert_eq!(try_compile_markdown(source),
+            Err(vec![ParserError::UnexpectedChar(Span::new(
+                Position { offset: 5, line: 0, column: 5 },
+                Position { offset: 6, line: 0, column: 6 },
+            ), '\0')]));
     }
 
-    final_value
-}
\ No newline at end of file
+    #[test]
+    fn parse_tests() {
+        let at = |offset, line, column| Position { offset, line, column };
+        let text = Inline::new(InlineKind::Text("Hi".into()),
+            Span::new(at(2, 0, 2), at(4, 0, 4)));
+        assert_eq!(parse_markdown("# Hi\n\n```c\nint a;\n```"), Document {
+            blocks: vec![
+                Block::new(BlockKind::Heading { level: 1, content: vec![text], id: None },
+                    Span::new(at(0, 0, 0), at(4, 0, 4))),
+                Block::new(BlockKind::CodeBlock {
+                    lang: Some("c".into()),
+                    code: "int a;\n".into(),
+                }, Span::new(at(6, 2, 0), at(21, 4, 3))),
+            ],
+        });
+        assert!(try_parse_markdown("`a` b").is_ok());
+
+        // The blocks one by one, the errors of the tokenizer are known from
+        // the start
+        let mut blocks = parse_markdown_blocks("a\0\n\n# b");
+        assert_eq!(blocks.errors().len(), 1);
+        assert!(matches!(blocks.next().map(|b| b.kind), Some(BlockKind::Paragraph(_))));
+        assert!(matches!(blocks.next().map(|b| b.kind), Some(BlockKind::Heading { level: 1, .. })));
+        assert_eq!(blocks.next(), None);
+        assert!(try_parse_markdown("```\na").is_err());
+    }
+}
@@ -1,300 +1,2256 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::iter::Peekable;
-use std::rc::Rc;
+use std::ops::Range;
 
-use super::tokenizer::{Token, Tokenizer};
+use super::ast::{self, Alignment, Block, BlockKind, Inline, InlineKind, TableCell};
+use super::tokenizer::{Token, TokenizerIterator};
 use super::errors::ParserError;
+use super::span::{Position, Span};
 
-/// Supported languages by the compiler, needed for the future adding of syntax
-/// highlight of code snippets
-#[allow(dead_code)]
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum CSLanguage {
-    /// Uknown language or not provided language so no syntax highlighting
-    Uknown,
-
-    /// The supported langs
-    /// TODO: Still not supported and needed more langs
-    Rust,
-    C,
-    Cpp,
+/// A link reference definition, `[label]: url "title"`, they can be anywhere
+/// in the document so they are collected before parsing
+#[derive(Debug, Clone, PartialEq)]
+struct LinkDefinition<'a> {
+    url: Cow<'a, str>,
+    title: Option<Cow<'a, str>>,
 }
 
-/// A `MDValue` is a definition that can be directly compiled to html,
-/// it is built by the `MDParser` from `Tokens`
-#[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq)]
-pub enum MDValue {
-    BigHeader(String),
-    MediumHeader(String),
-    SmallHeader(String),
-    VerySmallHeader(String),
-    CodeSnippet((CSLanguage, String)),
-    Text(String),
-    NewLine,
+impl LinkDefinition<'_> {
+    fn into_owned(self) -> LinkDefinition<'static> {
+        LinkDefinition {
+            url: Cow::Owned(self.url.into_owned()),
+            title: self.title.map(|title| Cow::Owned(title.into_owned())),
+        }
+    }
 }
 
-/// Markdown Parser Iterator that from the Tokens Iterator will yield
-/// MDValues
-pub struct MDParser<'a> {
-    source: Peekable<Tokenizer>,
+/// The part of the `text` in the `range`, borrowed if the `text` is
+fn part<'a>(text: &Cow<'a, str>, range: Range<usize>) -> Cow<'a, str> {
+    match text {
+        Cow::Borrowed(text) => Cow::Borrowed(&text[range]),
+        Cow::Owned(text) => Cow::Owned(text[range].to_owned()),
+    }
+}
 
-    /// The current line where we are parsing
-    line: usize,
+/// The text of the `source` at `span` if it's the `literal`, so it's
+/// borrowed, otherwise a copy of the `literal`
+fn source_text<'a>(source: &'a str, span: Span, literal: &str) -> Cow<'a, str> {
+    match source.get(span.range()) {
+        Some(text) if text == literal => Cow::Borrowed(text),
+        _ => Cow::Owned(literal.to_owned()),
+    }
+}
 
-    /// The current line-width where we are parsing
-    width: usize,
+/// Appends the `text` at `span` to the `prev` text at `prev_span`. While
+/// both are borrowed from the `source`, right at their spans, and one follows
+/// the other the joined text is still borrowed
+fn join<'a>(source: &'a str, prev: &mut Cow<'a, str>, prev_span: Span, text: Cow<'a, str>,
+    span: Span) {
+    let at_source = |text: &Cow<str>, span: Span| match text {
+        Cow::Borrowed(text) => source.get(span.range())
+            .is_some_and(|s| s.as_ptr() == text.as_ptr() && s.len() == text.len()),
+        Cow::Owned(_) => false,
+    };
+    if prev_span.end.offset == span.start.offset && at_source(prev, prev_span)
+        && at_source(&text, span) {
+        *prev = Cow::Borrowed(&source[prev_span.start.offset..span.end.offset]);
+    } else {
+        prev.to_mut().push_str(&text);
+    }
+}
 
-    /// Found errors with their corresponding line and line-width
-    errors: Vec<ParserError<'a>>,
+/// The paragraph tokens joined into text, runs of delimiters and brackets,
+/// they are kept with their span
+#[derive(Debug, Clone)]
+enum Piece<'a> {
+    Text(Cow<'a, str>),
+    /// A code snippet or a line break
+    Inline(Inline<'a>),
+    Delimiter(char, usize),
+    /// `[` or `![`
+    OpenBracket { image: bool },
+    CloseBracket,
 }
 
-impl MDParser<'_> {
-    /// Instantiate a new `MDParser` given the `Tokenizer` that contains the
-    /// tokens
-    pub fn new(tokens: Tokenizer) -> Self {
-        MDParser {
-            source: tokens.peekable(),
-            line: 0,
-            width: 0,
-            errors: Vec::new(),
+impl Piece<'_> {
+    fn literal(&self) -> Cow<'_, str> {
+        match self {
+            Piece::Text(text) => Cow::Borrowed(text),
+            Piece::Inline(inline) => match &inline.kind {
+                InlineKind::Code(code) => Cow::Owned(format!("`{}`", code)),
+                _ => Cow::Borrowed("\n"),
+            },
+            Piece::Delimiter(ch, n) => Cow::Owned(std::iter::repeat_n(*ch, *n).collect()),
+            Piece::OpenBracket { image: false } => Cow::Borrowed("["),
+            Piece::OpenBracket { image: true } => Cow::Borrowed("!["),
+            Piece::CloseBracket => Cow::Borrowed("]"),
         }
     }
-    fn extract_lang(code: String) -> (CSLanguage, String) {
-        let code_iter = code.lines();
-        match code.lines().next() {
-            Some("rust") => {
-                (CSLanguage::Rust, code_iter.skip(1).map(|s| {
-                    let mut s = s.to_owned();
-                    s.push('\n');
-                    s
-                }).collect())
+}
+
+/// A run of `*` or `_` that can open or close an emphasis following the
+/// CommonMark flanking rules
+#[derive(Debug, Clone, Copy)]
+struct Delimiter {
+    ch: char,
+
+    /// Delimiters still not consumed and the length of the original run
+    count: usize,
+    length: usize,
+
+    can_open: bool,
+    can_close: bool,
+
+    /// The span of the delimiters not consumed
+    span: Span,
+}
+
+impl Delimiter {
+    /// Builds the delimiter run of `length` `ch`s at `span` given the chars
+    /// around it, `None` means the start or the end of the paragraph
+    fn new(ch: char, length: usize, span: Span, prev: Option<char>, next: Option<char>) -> Self {
+        let is_space = |c: Option<char>| c.is_none_or(char::is_whitespace);
+        let is_punct = |c: Option<char>| c.is_some_and(|c| {
+            c.is_ascii_punctuation() || !(c.is_alphanumeric() || c.is_whitespace())
+        });
+
+        let left_flanking = !is_space(next)
+            && (!is_punct(next) || is_space(prev) || is_punct(prev));
+        let right_flanking = !is_space(prev)
+            && (!is_punct(prev) || is_space(next) || is_punct(next));
+
+        // An `_` can't open or close an emphasis inside a word,
+        // so `snake_case_words` stay as text
+        let (can_open, can_close) = if ch == '_' {
+            (left_flanking && (!right_flanking || is_punct(prev)),
+             right_flanking && (!left_flanking || is_punct(next)))
+        } else {
+            (left_flanking, right_flanking)
+        };
+
+        Delimiter { ch, count: length, length, can_open, can_close, span }
+    }
+
+    /// If `self` as opener can be matched with the `closer`, same char and the
+    /// "rule of 3" so `*foo**bar**baz*` nests instead of closing at `**`
+    fn matches(&self, closer: &Delimiter) -> bool {
+        self.ch == closer.ch
+            && !((self.can_close || closer.can_open)
+                && (self.length + closer.length).is_multiple_of(3)
+                && !(self.length.is_multiple_of(3) && closer.length.is_multiple_of(3)))
+    }
+
+    /// The delimiters not consumed as text
+    fn text<'a>(&self, source: &'a str) -> Inline<'a> {
+        let text: String = std::iter::repeat_n(self.ch, self.count).collect();
+        Inline::new(InlineKind::Text(source_text(source, self.span, &text)), self.span)
+    }
+}
+
+/// A `[` or `![` waiting for its `]`
+#[derive(Debug)]
+struct Bracket {
+    /// Index of its text in the inline values and of its piece
+    pos: usize,
+    piece: usize,
+
+    image: bool,
+}
+
+/// Normalizes a link label to compare it, case-insensitive and with the
+/// whitespace collapsed
+fn normalize_label(label: &str) -> String {
+    label.split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Skips the spaces at the start of `s` returning how many bytes were skipped
+fn skip_spaces(s: &str) -> usize {
+    s.len() - s.trim_start_matches([' ', '\t']).len()
+}
+
+/// Parses a link destination, `<url>` or an url without spaces and with
+/// balanced parenthesis, returning it and the number of bytes it took
+fn parse_link_destination(s: &str) -> Option<(&str, usize)> {
+    if let Some(rest) = s.strip_prefix('<') {
+        let end = rest.find(['>', '<', '\n'])?;
+        return if rest[end..].starts_with('>') {
+            Some((&rest[..end], end + 2))
+        } else {
+            None
+        };
+    }
+
+    let mut depth = 0;
+    let mut end = s.len();
+    for (i, ch) in s.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                end = i;
+                break;
             }
-            Some("c++") | Some("cpp") => {
-                (CSLanguage::Cpp, code_iter.skip(1).map(|s| {
-                    let mut s = s.to_owned();
-                    s.push('\n');
-                    s
-                }).collect())
+            ')' => depth -= 1,
+            _ if ch.is_whitespace() => {
+                end = i;
+                break;
             }
-            Some("c") => {
-                (CSLanguage::C, code_iter.skip(1).map(|s| {
-                    let mut s = s.to_owned();
-                    s.push('\n');
-                    s
-                }).collect())
+            _ => {}
+        }
+    }
+    if end == 0 || depth != 0 {
+        None
+    } else {
+        Some((&s[..end], end))
+    }
+}
+
+/// Parses a link title, `"title"`, `'title'` or `(title)`, returning it and the
+/// number of bytes it took
+fn parse_link_title(s: &str) -> Option<(&str, usize)> {
+    let close = match s.chars().next()? {
+        '"' => '"',
+        '\'' => '\'',
+        '(' => ')',
+        _ => return None,
+    };
+    let end = s[1..].find(close)? + 1;
+    Some((&s[1..end], end + 1))
+}
+
+/// Parses the `(url "title")` after the `]` of an inline link returning the
+/// number of bytes it took
+fn parse_inline_link(s: &str) -> Option<(LinkDefinition<'_>, usize)> {
+    let mut pos = 1 + skip_spaces(s.strip_prefix('(')?);
+    if s[pos..].starts_with(')') {
+        return Some((LinkDefinition { url: Cow::Borrowed(""), title: None }, pos + 1));
+    }
+
+    let (url, len) = parse_link_destination(&s[pos..])?;
+    pos += len;
+    let spaces = skip_spaces(&s[pos..]);
+    let mut title = None;
+    if spaces > 0 {
+        if let Some((t, len)) = parse_link_title(&s[pos + spaces..]) {
+            title = Some(Cow::Borrowed(t));
+            pos += spaces + len;
+        }
+    }
+    pos += skip_spaces(&s[pos..]);
+
+    if s[pos..].starts_with(')') {
+        Some((LinkDefinition { url: Cow::Borrowed(url), title }, pos + 1))
+    } else {
+        None
+    }
+}
+
+/// The most bytes of a link label, like in CommonMark
+const MAX_LABEL: usize = 999;
+
+/// Parses a `[label]` returning it and the number of bytes it took
+fn parse_link_label(s: &str) -> Option<(&str, usize)> {
+    let rest = s.strip_prefix('[')?;
+    let end = rest.find([']', '['])?;
+    if end <= MAX_LABEL && rest[end..].starts_with(']') {
+        Some((&rest[..end], end + 2))
+    } else {
+        None
+    }
+}
+
+/// Parses a whole line as a link reference definition `[label]: url "title"`
+fn parse_link_definition(line: &str) -> Option<(String, LinkDefinition<'_>)> {
+    let (label, mut pos) = parse_link_label(line)?;
+    if label.trim().is_empty() || !line[pos..].starts_with(':') {
+        return None;
+    }
+    pos += 1;
+    pos += skip_spaces(&line[pos..]);
+
+    let (url, len) = parse_link_destination(&line[pos..])?;
+    pos += len;
+    let spaces = skip_spaces(&line[pos..]);
+    let mut title = None;
+    if spaces > 0 {
+        if let Some((t, len)) = parse_link_title(&line[pos + spaces..]) {
+            title = Some(Cow::Borrowed(t));
+            pos += spaces + len;
+        }
+    }
+
+    if line[pos..].trim().is_empty() {
+        Some((normalize_label(label), LinkDefinition { url: Cow::Borrowed(url), title }))
+    } else {
+        None
+    }
+}
+
+/// Adds the `piece` at `span` to the `pieces`, joining the text and the
+/// delimiter runs
+fn push_piece<'a>(source: &'a str, pieces: &mut Vec<(Piece<'a>, Span)>, piece: Piece<'a>,
+    span: Span) {
+    match (pieces.last_mut(), piece) {
+        (Some((Piece::Text(prev), prev_span)), Piece::Text(text)) => {
+            join(source, prev, *prev_span, text, span);
+            prev_span.end = span.end;
+        }
+        (Some((Piece::Delimiter(prev, n), prev_span)), Piece::Delimiter(ch, 1))
+            if *prev == ch => {
+            *n += 1;
+            prev_span.end = span.end;
+        }
+        (_, piece) => pieces.push((piece, span)),
+    }
+}
+
+/// The part of the `source` at the `span` of a piece, its text can be
+/// longer than it, like the spaces of a tab
+fn piece_source(source: &str, span: Span) -> &str {
+    source.get(span.range()).unwrap_or_default()
+}
+
+/// Takes out the spaces at the end of a line of `pieces`, giving where the
+/// line ends without them and if they are a hard break, 2 spaces or more
+fn trim_line_end(source: &str, pieces: &mut Vec<(Piece, Span)>, end: Position)
+    -> (Position, bool) {
+    let mut start = pieces.last().map_or(end, |(_, span)| span.end);
+    let mut hard = false;
+    if let Some((Piece::Text(text), span)) = pieces.last_mut() {
+        // The end is taken from the source, the text can have the tabs as
+        // spaces
+        let src = piece_source(source, *span);
+        let kept = src.trim_end_matches([' ', '\t']);
+        hard = src[kept.len()..].ends_with("  ");
+        span.end = span.start.advance(kept);
+        *text = part(text, 0..text.trim_end_matches([' ', '\t']).len());
+        start = span.end;
+        if text.is_empty() {
+            pieces.pop();
+        }
+    }
+    (start, hard)
+}
+
+/// Takes out the spaces at the end of a line of `pieces` giving the line break
+/// that follows it, a hard one if the line ends with 2 spaces or a `\`. The
+/// break takes from the end of the text until `end`
+fn line_break<'a>(source: &str, pieces: &mut Vec<(Piece<'a>, Span)>, end: Position)
+    -> (Piece<'a>, Span) {
+    let (mut start, mut hard) = trim_line_end(source, pieces, end);
+    if let Some((Piece::Text(text), span)) = pieces.last_mut() {
+        // The `\` can't be escaped, the ones before it are pairs of `\\`
+        let src = piece_source(source, *span);
+        let backslashes = src.len() - src.trim_end_matches('\\').len();
+        if !hard && span.end == start && text.ends_with('\\') && backslashes % 2 == 1 {
+            *text = part(text, 0..text.len() - 1);
+            span.end = span.end.back(1);
+            start = span.end;
+            hard = true;
+            if text.is_empty() {
+                pieces.pop();
             }
-            _ => (CSLanguage::Uknown, code)
         }
     }
+    let span = Span::new(start, end);
+    let kind = if hard { InlineKind::HardBreak } else { InlineKind::SoftBreak };
+    (Piece::Inline(Inline::new(kind, span)), span)
 }
 
-/// The main usage of the MDParser, as Iterator
-impl<'a> Iterator for MDParser<'a> {
-    type Item = MDValue;
+/// Takes out the spaces around the `pieces` of a table cell or a header
+fn trim_pieces(source: &str, pieces: &mut [(Piece, Span)]) {
+    if let Some((Piece::Text(text), span)) = pieces.first_mut() {
+        let src = piece_source(source, *span);
+        let start = src.len() - src.trim_start_matches([' ', '\t']).len();
+        span.start = span.start.advance(&src[..start]);
+        let start = text.len() - text.trim_start_matches([' ', '\t']).len();
+        *text = part(text, start..text.len());
+    }
+    if let Some((Piece::Text(text), span)) = pieces.last_mut() {
+        span.end = span.start.advance(piece_source(source, *span).trim_end_matches([' ', '\t']));
+        *text = part(text, 0..text.trim_end_matches([' ', '\t']).len());
+    }
+}
 
-    /// TODO: Now with the double ended iterator I can simplify the paradigm
-    /// TODO(2): Well i didn't used the double ended iteartor for the moment :p
-    /// used here
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some(t) = self.source.peek() {
-            return Some(match t.clone() {
-                // 1 PAD FOUND
-                Token::Pad => {
-                    self.width += 1;
-                    self.source.next();
-                    if let Some(t2) = self.source.peek() {
-                        match t2.clone() {
-                            // PAD AND STRING FOUND
-                            Token::String(s) => {
-                                // FIXME: If string had spaces before they are not
-                                // added, so its fixed by the `+ 1` but its not
-                                // bulletproof
-                                self.width += s.len() + 1;
-                                self.source.next();
-                                MDValue::BigHeader(s.clone())
-                            },
-                            // 2 PAD FOUND
-                            Token::Pad => {
-                                self.width += 1;
-                                self.source.next();
-                                if let Some(t3) = self.source.peek() {
-                                    match t3.clone() {
-                                        // 2 PAD AND STRING FOUND
-                                        Token::String(s) => {
-                                            self.width += s.len() + 1;
-                                            self.source.next();
-                                            MDValue::MediumHeader(s.clone())
-                                        }
-                                        // 3 PAD FOUND
-                                        Token::Pad => {
-                                            self.width += 1;
-                                            self.source.next();
-                                            if let Some(t4) = self.source.peek() {
-                                                match t4.clone() {
-                                                    // 3 PAD AND STRING FOUND
-                                                    Token::String(s) => {
-                                                        self.width += s.len() + 1;
-                                                        self.source.next();
-                                                        MDValue::SmallHeader(s.clone())
-                                                    }
-                                                    // 4 PAD FOUND
-                                                    Token::Pad => {
-                                                        self.width += 1;
-                                                        self.source.next();
-                                                        if let Some(t5) = self.source.peek() {
-                                                            // 4 PAD AND STRING
-                                                            if let Token::String(s) = t5.clone() {
-                                                                self.width += s.len() + 1;
-                                                                self.source.next();
-                                                                MDValue::VerySmallHeader(s.clone())
-                                                            } else {
-                                                                panic!("Expected string after 4 pads");
-                                                            }
-                                                        } else {
-                                                            continue;
-                                                        }
-                                                    }
-                                                    _ => panic!("asfaf"),
-                                                }
-                                            } else {
-                                                continue;
-                                            }
-                                        }
-                                        _ => panic!("Expected Pad or String after 2 pads but got {:?}", t3),
-                                    } 
-                                } else {
-                                    continue;
-                                }
-                            }
-                            _ => {
-                                self.errors.push(
-                                    ParserError::ExpectedButGot((self.line, self.width), &["Token::Pad", "Token::String"], t2.clone())
-                                );
-                                continue;
-                            }
-                        }
-                    } else {
+/// Takes out the `{#id}` at the end of the trimmed `pieces` of a header,
+/// giving the id. It needs an space before it unless it's the whole text
+fn heading_id<'a>(source: &str, pieces: &mut [(Piece<'a>, Span)]) -> Option<Cow<'a, str>> {
+    let Some((Piece::Text(text), span)) = pieces.last_mut() else {
+        return None;
+    };
+    let start = text.strip_suffix('}')?.rfind("{#")?;
+    let id = part(text, start + 2..text.len() - 1);
+    if id.is_empty() || id.contains(|ch: char| ch.is_whitespace() || ch == '{' || ch == '}')
+        || !(start == 0 || text[..start].ends_with([' ', '\t'])) {
+        return None;
+    }
+
+    // The `{` can't be escaped
+    let src = piece_source(source, *span);
+    let attribute = src.rfind("{#")?;
+    let backslashes = attribute - src[..attribute].trim_end_matches('\\').len();
+    if backslashes % 2 == 1 {
+        return None;
+    }
+    span.end = span.start.advance(&src[..attribute]);
+    *text = part(text, 0..start);
+    trim_pieces(source, pieces);
+    Some(id)
+}
+
+/// The span of the `content` of a block, or `default` if it's empty
+fn content_span(content: &[Inline], default: Span) -> Span {
+    match (content.first(), content.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => default,
+    }
+}
+
+/// Parses the inline content of a paragraph or a header, its text is
+/// borrowed from the `source` when it can
+fn parse_inline<'a>(source: &'a str, pieces: Vec<(Piece<'a>, Span)>,
+    definitions: &HashMap<String, LinkDefinition<'a>>) -> Vec<Inline<'a>> {
+    merge_text(source, parse_inline_tree(source, pieces, definitions))
+}
+
+/// Like `parse_inline` but the delimiters that were not matched are left as
+/// texts between the other texts
+fn parse_inline_tree<'a>(source: &'a str, mut pieces: Vec<(Piece<'a>, Span)>,
+    definitions: &HashMap<String, LinkDefinition<'a>>) -> Vec<Inline<'a>> {
+    let mut values: Vec<Inline> = Vec::new();
+    // The delimiters that can open with the index of their text in `values`
+    let mut openers: Vec<(usize, Delimiter)> = Vec::new();
+    let mut brackets: Vec<Bracket> = Vec::new();
+    // Links can't contain other links, so once one is found the brackets
+    // before it are deactivated, the ones below this index
+    let mut active_from = 0;
+
+    // The literals of the pieces joined, with where each one starts, so the
+    // text after a `]` is sliced instead of built again for each one
+    let mut literals = String::new();
+    let mut starts = Vec::with_capacity(pieces.len() + 1);
+    for (piece, _) in &pieces {
+        starts.push(literals.len());
+        literals.push_str(&piece.literal());
+    }
+    starts.push(literals.len());
+
+    let mut i = 0;
+    while i < pieces.len() {
+        let (piece, span) = pieces[i].clone();
+        match piece {
+            Piece::Text(text) => values.push(Inline::new(InlineKind::Text(text), span)),
+            Piece::Inline(inline) => values.push(inline),
+            Piece::OpenBracket { image } => {
+                brackets.push(Bracket { pos: values.len(), piece: i, image });
+                let text = source_text(source, span, &pieces[i].0.literal());
+                values.push(Inline::new(InlineKind::Text(text), span));
+            }
+            Piece::CloseBracket => {
+                let bracket = brackets.pop();
+                let active = brackets.len() >= active_from;
+                active_from = active_from.min(brackets.len());
+                let bracket = match bracket {
+                    Some(bracket) if active => bracket,
+                    _ => {
+                        let text = source_text(source, span, "]");
+                        values.push(Inline::new(InlineKind::Text(text), span));
+                        i += 1;
                         continue;
                     }
+                };
+
+                let rest = &literals[starts[i + 1]..];
+                let text_span = Span::new(pieces[bracket.piece].1.end, span.start);
+                let text = &literals[starts[bracket.piece + 1]..starts[i]];
+                // The text is only needed as a label, that can't be too long
+                let text = || Some(text)
+                    .filter(|text| text.len() <= MAX_LABEL)
+                    .map(|text| source_text(source, text_span, text));
+                let lookup = |label: &str| definitions.get(&normalize_label(label));
+
+                // The `len` bytes of the link after the `]`, if they are the
+                // same in the source the link is borrowed from there
+                let after = source.get(span.end.offset..).unwrap_or_default();
+                let borrow = |len: usize| after.get(..len).filter(|s| rest.get(..len) == Some(s));
+
+                // Inline `(url)`, full `[label]`, collapsed `[]` or shortcut
+                let link = if let Some((def, len)) = parse_inline_link(rest) {
+                    let def = match borrow(len).and_then(parse_inline_link) {
+                        Some((def, _)) => def,
+                        None => def.into_owned(),
+                    };
+                    Some((def, None, len))
+                } else if let Some((label, len)) = parse_link_label(rest) {
+                    let label = match borrow(len) {
+                        _ if label.is_empty() => text(),
+                        Some(after) => Some(Cow::Borrowed(&after[1..len - 1])),
+                        None => Some(Cow::Owned(label.to_owned())),
+                    };
+                    label.and_then(|label| {
+                        lookup(&label).map(|def| (def.clone(), Some(label), len))
+                    })
+                } else {
+                    text().and_then(|text| lookup(&text).map(|def| (def.clone(), Some(text), 0)))
+                };
+                let (def, label, mut len) = match link {
+                    Some(link) => link,
+                    None => {
+                        let text = source_text(source, span, "]");
+                        values.push(Inline::new(InlineKind::Text(text), span));
+                        i += 1;
+                        continue;
+                    }
+                };
+
+                // Skip the pieces that were part of the link
+                let mut end = span.end;
+                i += 1;
+                while len > 0 && i < pieces.len() {
+                    let literal = pieces[i].0.literal();
+                    if literal.len() <= len {
+                        len -= literal.len();
+                        end = pieces[i].1.end;
+                        i += 1;
+                    } else {
+                        end = pieces[i].1.start.advance(&literal[..len]);
+                        let text = match &pieces[i].0 {
+                            Piece::Text(text) => part(text, len..text.len()),
+                            _ => Cow::Owned(literal[len..].to_owned()),
+                        };
+                        let rest = Span::new(end, pieces[i].1.end);
+                        pieces[i] = (Piece::Text(text), rest);
+                        starts[i] += len;
+                        len = 0;
+                    }
                 }
-                Token::NewLine => {
-                    self.line += 1;
-                    self.width = 0;
-                    self.source.next();
-                    MDValue::NewLine
-                },
-                Token::Asterisk => {
-                    // Implement the bold text
-                    todo!()
+
+                // The delimiters inside the link text can't be closed anymore
+                openers.retain(|(pos, _)| *pos < bracket.pos);
+                let children: Vec<Inline> = values.drain(bracket.pos + 1..).collect();
+                let start = values.pop().map_or(span.start, |open| open.span.start);
+                let kind = if bracket.image {
+                    let alt = match children.as_slice() {
+                        [Inline { kind: InlineKind::Text(text), .. }] => text.clone(),
+                        children => Cow::Owned(ast::text(children)),
+                    };
+                    InlineKind::Image {
+                        alt,
+                        src: def.url,
+                        title: def.title,
+                        label,
+                    }
+                } else {
+                    InlineKind::Link { children, url: def.url, title: def.title, label }
+                };
+                values.push(Inline::new(kind, Span::new(start, end)));
+                if !bracket.image {
+                    active_from = brackets.len();
                 }
-                Token::ReversedQuote => {
-                    self.width += 1;
-                    self.source.next();
-                    if let Some(t2) = self.source.peek() {
-                        match t2.clone() {
-                            Token::ReversedQuote => {
-                                self.width += 1;
-                                self.source.next();
-                                if let Some(Token::ReversedQuote) = self.source.peek() {
-                                    self.width += 1;
-                                    self.source.next();
-                                    if let Some(Token::Code(c)) = self.source.peek() {
-                                        let (lang, c) = MDParser::extract_lang(c.clone());
-                                        // TODO: Create different syntax highlighters
-                                        // POSSIBLE_ERROR
-                                        self.line += c.lines().count() + 2;
-                                        self.width = 0;
-                                        self.source.next();
-                                        match (self.source.next(), self.source.next(), self.source.next()) {
-                                            (Some(Token::ReversedQuote), Some(Token::ReversedQuote), Some(Token::ReversedQuote)) => {
-                                                self.width += 3;
-                                                MDValue::CodeSnippet((lang, c))
-                                            }
-                                            (a,b,c) => panic!("Expected 3 ReversedQuotes after Code, got {:?} {:?} {:?}", a, b, c),
-                                        }
-                                    } else {
-                                        panic!("Expected Code after 3 ReversedQuotes");
-                                    }
-                                } else {
-                                    panic!("Expected another ReversedQuote after ReversedQuote");
-                                }
-                            }
-                            Token::Code(c) => {
-                                // TODO: Skip code and end_quote (checking if there is)
-                                self.source.next();
-                                if let Some(Token::ReversedQuote) = self.source.peek() {
-                                    self.width += c.len();
-                                    self.source.next();
-                                    MDValue::CodeSnippet((CSLanguage::Uknown, c.clone()))
-                                } else {
-                                    panic!("Expected closing ReversedQuote for Code but got");
-                                }
-                            }
-                            _ => panic!("Expected another ReversedQuoute or Code but got {:?}", t2
-                        
-                        ),
-                        }
+                continue;
+            }
+            Piece::Delimiter(ch, length) => {
+                let prev = if i == 0 {
+                    None
+                } else {
+                    pieces[i - 1].0.literal().chars().last()
+                };
+                let next = pieces.get(i + 1).and_then(|(p, _)| p.literal().chars().next());
+                let mut closer = Delimiter::new(ch, length, span, prev, next);
+
+                // The emphasis can't cross the start of a possible link
+                let bottom = brackets.last().map(|b| b.pos);
+                while closer.can_close && closer.count > 0 {
+                    let idx = match openers.iter().rposition(|(pos, o)| {
+                        bottom.is_none_or(|b| *pos > b) && o.matches(&closer)
+                    }) {
+                        Some(idx) => idx,
+                        None => break,
+                    };
+                    // The openers between can't be closed anymore
+                    openers.truncate(idx + 1);
+                    let (pos, opener) = openers.last_mut().unwrap();
+
+                    // The opener is consumed from its end and the closer from
+                    // its start
+                    let used = if opener.count >= 2 && closer.count >= 2 { 2 } else { 1 };
+                    opener.count -= used;
+                    closer.count -= used;
+                    let start = opener.span.start.forward(opener.count);
+                    closer.span.start = closer.span.start.forward(used);
+                    let children = values.drain(*pos + 1..).collect();
+                    let kind = if used == 2 {
+                        InlineKind::Strong(children)
                     } else {
-                        continue;
+                        InlineKind::Emphasis(children)
+                    };
+                    values.push(Inline::new(kind, Span::new(start, closer.span.start)));
+
+                    if opener.count == 0 {
+                        values.remove(*pos);
+                        openers.pop();
+                    } else {
+                        opener.span.end = start;
+                        values[*pos] = opener.text(source);
                     }
                 }
-                Token::String(s) => {
-                    self.width += s.len();
-                    self.source.next();
-                    MDValue::Text(s)
+
+                // Each emphasis inside another takes a char of an opener, so
+                // few of them keep a deep input from overflowing the stack
+                let open: usize = openers.iter().map(|(_, o)| o.count).sum();
+                if closer.count > 0 {
+                    if closer.can_open && open + closer.count <= MAX_DEPTH {
+                        openers.push((values.len(), closer));
+                    }
+                    values.push(closer.text(source));
                 }
-                _ => panic!("!! Unexpected, got {:?}", t),
-            });
+            }
+        }
+        i += 1;
+    }
+
+    values
+}
+
+/// Joins the consecutive texts of the `values` and of their children
+fn merge_text<'a>(source: &'a str, values: Vec<Inline<'a>>) -> Vec<Inline<'a>> {
+    let mut result: Vec<Inline> = Vec::with_capacity(values.len());
+    for Inline { kind, span } in values {
+        let kind = match kind {
+            InlineKind::Emphasis(children) => {
+                InlineKind::Emphasis(merge_text(source, children))
+            }
+            InlineKind::Strong(children) => InlineKind::Strong(merge_text(source, children)),
+            InlineKind::Link { children, url, title, label } => {
+                InlineKind::Link { children: merge_text(source, children), url, title, label }
+            }
+            kind => kind,
+        };
+        match (result.last_mut(), kind) {
+            (_, InlineKind::Text(text)) if text.is_empty() => {}
+            (Some(Inline { kind: InlineKind::Text(prev), span: prev_span }),
+                InlineKind::Text(text)) => {
+                join(source, prev, *prev_span, text, span);
+                prev_span.end = span.end;
+            }
+            (_, kind) => result.push(Inline::new(kind, span)),
         }
+    }
+    result
+}
+
+/// A line of tokens without its `Token::NewLine`, the blank lines before it
+/// and its indentation are taken out of the tokens
+#[derive(Debug, Clone, PartialEq)]
+struct Line<'a> {
+    /// Number of blank lines before this one
+    blank_before: usize,
+
+    indent: usize,
+
+    /// The columns already taken out by the list items that contain the line,
+    /// the code snippets still have them
+    offset: usize,
+
+    /// The `>`s already taken out by the block quotes that contain the line,
+    /// the code snippets also have them
+    quotes: usize,
+
+    tokens: Vec<Token<'a>>,
+
+    /// The span of each token
+    spans: Vec<Span>,
+
+    /// If the line ends with a `Token::NewLine`
+    newline: bool,
+
+    /// The end of the line after its `Token::NewLine`
+    end: Position,
+}
+
+impl<'a> Line<'a> {
+    /// Takes out the first token
+    fn remove_first(&mut self) -> Token<'a> {
+        self.spans.remove(0);
+        self.tokens.remove(0)
+    }
+
+    /// The span of the tokens from `start` until the end of the line, without
+    /// the `Token::NewLine`
+    fn span(&self, start: usize) -> Span {
+        self.tokens_span(start..self.tokens.len())
+    }
+
+    /// The span of the tokens in the `range`, without tokens it's empty at
+    /// the start of the next one
+    fn tokens_span(&self, range: Range<usize>) -> Span {
+        if range.is_empty() {
+            Span::at(self.spans.get(range.start).map_or(self.end, |span| span.start))
+        } else {
+            self.spans[range.start].to(self.spans[range.end - 1])
+        }
+    }
+
+    /// If the line starts with text, not a header, a code fence, a list
+    /// marker or a thematic break, so it can be the continuation of the text
+    /// of a list item
+    fn is_text(&self) -> bool {
+        is_text(&self.tokens)
+    }
+
+    /// If the text of the line, after its list and quote markers, can be
+    /// continued by the next line
+    fn ends_with_text(&self) -> bool {
+        let start = self.tokens.iter()
+            .take_while(|t| matches!(t, Token::Bullet(_) | Token::ListNumber(..)
+                | Token::Indent(_) | Token::BlockQuote))
+            .count();
+        is_text(&self.tokens[start..])
+    }
+
+    /// If the line continues the paragraph of the line before it, an ordered
+    /// list can only interrupt a paragraph if it starts at 1 and a list item
+    /// can't be empty
+    fn continues_paragraph(&self) -> bool {
+        if self.blank_before > 0 {
+            return false;
+        }
+        match self.tokens.first() {
+            Some(Token::ListNumber(start, _)) if *start != 1 => true,
+            Some(Token::Bullet(_)) | Some(Token::ListNumber(..)) => {
+                self.tokens[1..].iter().all(|t| matches!(t, Token::Indent(_)))
+            }
+            _ => self.is_text(),
+        }
+    }
+}
+
+/// If the `tokens` of a line are a thematic break, 3 or more `-`, `*` or `_`
+/// with maybe spaces between them. The escaped `\*` and `\_` are strings
+fn is_thematic_break(tokens: &[Token]) -> bool {
+    let marks = tokens.iter().all(|t| match t {
+        Token::Bullet(_) | Token::Indent(_) | Token::Asterisk | Token::LowBar => true,
+        Token::String(s) => s.chars().all(|ch| matches!(ch, '-' | ' ' | '\t')),
+        _ => false,
+    });
+    let text: String = tokens.iter().map(Token::literal).collect();
+    let mut chars = text.chars().filter(|ch| !matches!(ch, ' ' | '\t'));
+    let first = chars.next();
+    marks && matches!(first, Some('-' | '*' | '_'))
+        && chars.clone().all(|ch| Some(ch) == first) && chars.count() >= 2
+}
+
+/// If the `tokens` of a line start with text, see `Line::is_text`
+fn is_text(tokens: &[Token]) -> bool {
+    if is_thematic_break(tokens) {
+        return false;
+    }
+    match tokens.first() {
+        None | Some(Token::Pad) | Some(Token::Bullet(_)) | Some(Token::ListNumber(..))
+        | Some(Token::BlockQuote) => false,
+        Some(Token::ReversedQuote) => {
+            tokens.iter().take_while(|t| **t == Token::ReversedQuote).count() < 3
+        }
+        _ => true,
+    }
+}
+
+/// The level of the setext header underlined by the `line`, 1 for `===` and
+/// 2 for `---`
+fn setext_level(line: &Line) -> Option<usize> {
+    if line.blank_before > 0 || line.indent >= 4 {
+        return None;
+    }
+    let text: String = line.tokens.iter().map(Token::literal).collect();
+    let text = text.trim_end();
+    if text.is_empty() {
+        None
+    } else if text.bytes().all(|b| b == b'=') {
+        Some(1)
+    } else if text.bytes().all(|b| b == b'-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Iterator that groups the tokens, with their spans, into `Line`s taking
+/// them from the `source` only as they are needed
+struct Lines<I> {
+    source: I,
+
+    /// The end of the last token
+    end: Position,
+}
+
+impl<'a, I> Iterator for Lines<I>
+where
+    I: Iterator<Item = (Token<'a>, Span)>,
+{
+    type Item = Line<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut blank_before = 0;
+        loop {
+            let mut tokens = Vec::new();
+            let mut spans = Vec::new();
+            let mut newline = false;
+            for (t, span) in self.source.by_ref() {
+                self.end = span.end;
+                if t == Token::NewLine {
+                    newline = true;
+                    break;
+                }
+                tokens.push(t);
+                spans.push(span);
+            }
+
+            let mut line = Line {
+                blank_before,
+                indent: 0,
+                offset: 0,
+                quotes: 0,
+                tokens,
+                spans,
+                newline,
+                end: self.end,
+            };
+            if let Some(&Token::Indent(n)) = line.tokens.first() {
+                line.remove_first();
+                line.indent = n;
+            }
+            if !line.tokens.is_empty() {
+                return Some(line);
+            } else if newline {
+                blank_before += 1;
+            } else if blank_before > 0 {
+                // The blank lines at the end are an empty line
+                line.blank_before -= 1;
+                line.newline = true;
+                return Some(line);
+            } else {
+                return None;
+            }
+        }
+    }
+}
+
+/// Splits the first line of a list item, that starts with the list marker,
+/// returning the column where the content of the item starts and the content
+/// of the line
+fn item_content(mut line: Line<'_>) -> (usize, Line<'_>) {
+    let marker = line.remove_first();
+    let mut column = line.indent + marker.literal().chars().count();
+
+    // The content starts after 1 to 4 spaces, with more the rest is
+    // indentation of the content
+    let spaces = match line.tokens.first() {
+        Some(&Token::Indent(n)) => {
+            line.remove_first();
+            n
+        }
+        _ => 0,
+    };
+    line.indent = 0;
+    if (1..=4).contains(&spaces) {
+        column += spaces;
+    } else {
+        column += 1;
+        line.indent = spaces.saturating_sub(1);
+    }
+
+    line.offset += column;
+    line.blank_before = 0;
+    (column, line)
+}
+
+/// The ranges of the tokens of each cell of a table row, the `|`s at the
+/// start and at the end of the row are optional
+fn table_cells(line: &Line) -> Vec<Range<usize>> {
+    let mut cells = Vec::new();
+    let mut start = 0;
+    for (i, token) in line.tokens.iter().enumerate() {
+        if *token == Token::Pipe {
+            cells.push(start..i);
+            start = i + 1;
+        }
+    }
+    cells.push(start..line.tokens.len());
+
+    let blank = |cell: &Range<usize>| {
+        line.tokens[cell.clone()].iter().all(|t| t.literal().trim().is_empty())
+    };
+    if cells.len() > 1 && blank(&cells[cells.len() - 1]) {
+        cells.pop();
+    }
+    if cells.len() > 1 && blank(&cells[0]) {
+        cells.remove(0);
+    }
+    cells
+}
+
+/// If the `header` line and the `next` one start a table giving the alignment
+/// of its columns, the `next` one must have a cell of `-`s for each cell of
+/// the `header` and both need a `|`
+fn table_alignments(header: &Line, next: Option<&Line>) -> Option<Vec<Alignment>> {
+    let next = next.filter(|next| next.blank_before == 0)?;
+    if !header.tokens.contains(&Token::Pipe) || !next.tokens.contains(&Token::Pipe) {
+        return None;
+    }
+
+    let alignments = table_cells(next).into_iter()
+        .map(|cell| {
+            let text: String = next.tokens[cell].iter().map(Token::literal).collect();
+            let text = text.trim();
+            let left = text.starts_with(':');
+            let right = text.len() > 1 && text.ends_with(':');
+            let dashes = &text[left as usize..text.len() - right as usize];
+            if dashes.is_empty() || !dashes.bytes().all(|b| b == b'-') {
+                return None;
+            }
+            Some(match (left, right) {
+                (false, false) => Alignment::None,
+                (true, false) => Alignment::Left,
+                (true, true) => Alignment::Center,
+                (false, true) => Alignment::Right,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if alignments.len() == table_cells(header).len() {
+        Some(alignments)
+    } else {
         None
     }
+}
+
+/// Takes out the `>` of a line of a block quote and the space after it
+fn quote_content(mut line: Line<'_>) -> Line<'_> {
+    line.remove_first();
+    line.indent = match line.tokens.first() {
+        Some(&Token::Indent(n)) => {
+            line.remove_first();
+            n - 1
+        }
+        _ => 0,
+    };
+    line.quotes += 1;
+    line.blank_before = 0;
+    line
+}
+
+/// Takes out of a line of a fenced code the `>`s of the `quotes` that
+/// contain it, with the space after them, and up to `indent` spaces
+fn strip_code_line(mut line: &str, quotes: usize, indent: usize) -> &str {
+    for _ in 0..quotes {
+        match line.trim_start_matches(' ').strip_prefix('>') {
+            Some(rest) => line = rest.strip_prefix(' ').unwrap_or(rest),
+            None => break,
+        }
+    }
+    let spaces = line.len() - line.trim_start_matches(' ').len();
+    &line[spaces.min(indent)..]
+}
+
+/// The state of the parser shared by all the blocks, also the ones inside
+/// the list items
+struct Context<'a> {
+    /// The source of the tokens, the text of the blocks is borrowed from it
+    source: &'a str,
+
+    /// Found errors with their corresponding span
+    errors: Vec<ParserError>,
+
+    /// The link reference definitions of the whole document by their label
+    definitions: HashMap<String, LinkDefinition<'a>>,
+
+    /// How many lists and quotes the blocks being parsed are in
+    depth: usize,
+}
+
+/// The most lists and quotes, or emphasis, one inside another, the markers
+/// of the deeper ones are just text, so a deep input can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+impl<'a> Context<'a> {
+    /// Parses the next block of the `lines`, a header, a code block, a
+    /// paragraph or a whole list. A line can also give no block, like a link
+    /// definition, or more than one
+    fn parse_block<I>(&mut self, lines: &mut Peekable<I>) -> Option<Vec<Block<'a>>>
+    where
+        I: Iterator<Item = Line<'a>>,
+    {
+        let line = lines.next()?;
+        let mut blocks = Vec::new();
+        match line.tokens.first() {
+            Some(_) if line.indent < 4 && is_thematic_break(&line.tokens) => {
+                blocks.push(Block::new(BlockKind::ThematicBreak, line.span(0)));
+            }
+            Some(Token::Bullet(_)) | Some(Token::ListNumber(..)) if self.depth < MAX_DEPTH => {
+                blocks.push(self.parse_list(line, lines));
+            }
+            Some(Token::BlockQuote) if self.depth < MAX_DEPTH => {
+                blocks.push(self.parse_quote(line, lines));
+            }
+            Some(Token::Pad) => blocks.push(self.parse_header(&line)),
+            Some(Token::ReversedQuote) if !line.is_text() => {
+                let (block, len) = self.parse_fence(&line);
+                blocks.push(block);
+                // The text after the fence
+                let mut pieces = Vec::new();
+                self.line_pieces(&line, len..line.tokens.len(), &mut pieces);
+                trim_line_end(self.source, &mut pieces, line.end);
+                if !pieces.is_empty() {
+                    let content = parse_inline(self.source, pieces, &self.definitions);
+                    let span = content_span(&content, line.span(len));
+                    blocks.push(Block::new(BlockKind::Paragraph(content), span));
+                }
+            }
+            Some(Token::OpenBracket) if self.is_toc_marker(&line, lines.peek()) => {
+                blocks.push(Block::new(BlockKind::TableOfContents, line.span(0)));
+            }
+            Some(Token::OpenBracket) if self.is_definition(&line) => {}
+            Some(_) => match table_alignments(&line, lines.peek()) {
+                Some(alignments) => blocks.push(self.parse_table(line, alignments, lines)),
+                None => self.parse_paragraph(line, lines, &mut blocks),
+            },
+            None => {}
+        }
+        Some(blocks)
+    }
+
+    /// Parses all the `lines` of a list item, giving also if there are blank
+    /// lines between its blocks
+    fn parse_blocks(&mut self, lines: Vec<Line<'a>>) -> (Vec<Block<'a>>, bool) {
+        let mut lines = lines.into_iter().peekable();
+        let mut blocks = Vec::new();
+        let mut loose = false;
+        self.depth += 1;
+        while let Some(line) = lines.peek() {
+            loose |= !blocks.is_empty() && line.blank_before > 0;
+            blocks.extend(self.parse_block(&mut lines).unwrap_or_default());
+        }
+        self.depth -= 1;
+        (blocks, loose)
+    }
+
+    /// Parses a list that starts at the `first` line, the one with the marker
+    /// of the first item, taking the rest of the items from the `lines`. The
+    /// list is loose if there are blank lines between its items or between the
+    /// blocks of an item
+    fn parse_list<I>(&mut self, first: Line<'a>, lines: &mut Peekable<I>) -> Block<'a>
+    where
+        I: Iterator<Item = Line<'a>>,
+    {
+        let marker = first.tokens[0].clone();
+        let mut span = first.spans[0];
+        let same_list = |t: &Token| match (&marker, t) {
+            (Token::Bullet(a), Token::Bullet(b)) => a == b,
+            (Token::ListNumber(_, a), Token::ListNumber(_, b)) => a == b,
+            _ => false,
+        };
+
+        let mut items = Vec::new();
+        let mut tight = true;
+        let mut line = first;
+        loop {
+            let marker_span = line.spans[0];
+            let (column, content) = item_content(line);
+            let mut open_text = content.ends_with_text();
+            let mut item = vec![content];
+            while let Some(next) = lines.peek() {
+                if next.tokens.is_empty() {
+                    // The blank lines at the end
+                    break;
+                } else if next.indent >= column {
+                    let mut next = lines.next().unwrap();
+                    next.indent -= column;
+                    next.offset += column;
+                    open_text = next.ends_with_text();
+                    item.push(next);
+                } else if next.blank_before == 0 && open_text && next.is_text() {
+                    // Lazy continuation of the text of the item
+                    item.push(lines.next().unwrap());
+                } else {
+                    break;
+                }
+            }
+
+            let (blocks, loose) = self.parse_blocks(item);
+            tight &= !loose;
+            span.end = blocks.last().map_or(marker_span.end, |block| block.span.end);
+            items.push(blocks);
+
+            match lines.peek() {
+                Some(next) if next.tokens.first().is_some_and(same_list)
+                    && !is_thematic_break(&next.tokens) => {
+                    tight &= next.blank_before == 0;
+                    line = lines.next().unwrap();
+                }
+                _ => break,
+            }
+        }
+
+        let start = match marker {
+            Token::ListNumber(start, _) => Some(start),
+            _ => None,
+        };
+        Block::new(BlockKind::List { start, tight, items }, span)
+    }
+
+    /// Parses a block quote that starts at the `first` line taking the rest
+    /// of its lines, the ones that start with `>` and the ones that continue
+    /// its text. A blank line ends it
+    fn parse_quote<I>(&mut self, first: Line<'a>, lines: &mut Peekable<I>) -> Block<'a>
+    where
+        I: Iterator<Item = Line<'a>>,
+    {
+        let mut span = first.span(0);
+        let mut content = Vec::new();
+        let mut open_text = false;
+        let mut blank_before = 0;
+        let mut next = Some(first);
+        while let Some(line) = next {
+            span.end = line.span(0).end;
+            if line.tokens.first() == Some(&Token::BlockQuote) {
+                let mut line = quote_content(line);
+                if line.tokens.is_empty() {
+                    // A line with only the `>`
+                    blank_before += 1;
+                    open_text = false;
+                } else {
+                    line.blank_before = blank_before;
+                    blank_before = 0;
+                    open_text = line.ends_with_text();
+                    content.push(line);
+                }
+            } else {
+                // Lazy continuation of the text
+                content.push(line);
+            }
+
+            next = lines.next_if(|line| {
+                line.blank_before == 0 && !line.tokens.is_empty()
+                    && ((line.tokens[0] == Token::BlockQuote && line.indent < 4)
+                        || (open_text && line.continues_paragraph()))
+            });
+        }
+
+        let (blocks, _) = self.parse_blocks(content);
+        Block::new(BlockKind::Quote(blocks), span)
+    }
+
+    /// If the `line` is a `[TOC]` or `[[toc]]` alone, in any case, that is not
+    /// a link to a `[toc]` definition nor the start of a paragraph
+    fn is_toc_marker(&self, line: &Line, next: Option<&Line>) -> bool {
+        let text: String = line.tokens.iter().map(Token::literal).collect();
+        let text = text.trim_end().to_lowercase();
+        (text == "[[toc]]" || (text == "[toc]" && !self.definitions.contains_key("toc")))
+            && next.is_none_or(|next| !next.continues_paragraph() && setext_level(next).is_none())
+    }
+
+    /// If the `line` is a link definition, they are already collected
+    fn is_definition(&self, line: &Line) -> bool {
+        let text: String = line.tokens.iter().map(Token::literal).collect();
+        !line.tokens.contains(&Token::ReversedQuote) && parse_link_definition(&text).is_some()
+    }
+
+    /// Parses a table with the `header` line, its next line is the delimiter
+    /// row with the `alignments` and the rows are the lines of text after it
+    fn parse_table<I>(&mut self, header: Line<'a>, alignments: Vec<Alignment>,
+        lines: &mut Peekable<I>) -> Block<'a>
+    where
+        I: Iterator<Item = Line<'a>>,
+    {
+        let mut span = header.span(0);
+        if let Some(delimiter) = lines.next() {
+            span.end = delimiter.span(0).end;
+        }
+        let header = self.table_row(&header, alignments.len());
+
+        let mut rows = Vec::new();
+        while let Some(line) = lines.next_if(|line| line.blank_before == 0 && line.is_text()) {
+            span.end = line.span(0).end;
+            rows.push(self.table_row(&line, alignments.len()));
+        }
+        Block::new(BlockKind::Table { alignments, header, rows }, span)
+    }
+
+    /// Parses the cells of a table row, it always gives `columns` cells
+    fn table_row(&mut self, line: &Line<'a>, columns: usize) -> Vec<TableCell<'a>> {
+        let mut cells = Vec::with_capacity(columns);
+        for range in table_cells(line).into_iter().take(columns) {
+            let mut pieces = Vec::new();
+            self.line_pieces(line, range.clone(), &mut pieces);
+            trim_pieces(self.source, &mut pieces);
+            let content = parse_inline(self.source, pieces, &self.definitions);
+            let span = content_span(&content, line.tokens_span(range));
+            cells.push(TableCell { content, span });
+        }
+        // The missing cells are empty
+        while cells.len() < columns {
+            cells.push(TableCell { content: Vec::new(), span: Span::at(line.span(0).end) });
+        }
+        cells
+    }
+
+    /// Parses a paragraph that starts at the `first` line taking the lines
+    /// that continue it, into the `blocks`. If they are followed by a `===` or
+    /// `---` line they are a setext header instead, and a table can
+    /// interrupt them
+    fn parse_paragraph<I>(&mut self, first: Line<'a>, lines: &mut Peekable<I>,
+        blocks: &mut Vec<Block<'a>>)
+    where
+        I: Iterator<Item = Line<'a>>,
+    {
+        let mut pieces = Vec::new();
+        let mut span = first.span(0);
+        self.line_pieces(&first, 0..first.tokens.len(), &mut pieces);
+        let mut end = first.end;
+        let mut level = None;
+        let mut table = None;
+        loop {
+            if let Some(underline) = lines.peek() {
+                level = setext_level(underline);
+                if level.is_some() {
+                    span.end = underline.span(0).end;
+                    lines.next();
+                    break;
+                }
+            }
+            let Some(line) = lines.next_if(Line::continues_paragraph) else {
+                break;
+            };
+            if let Some(alignments) = table_alignments(&line, lines.peek()) {
+                table = Some(self.parse_table(line, alignments, lines));
+                break;
+            }
+            let line_break = line_break(self.source, &mut pieces, end);
+            pieces.push(line_break);
+            self.line_pieces(&line, 0..line.tokens.len(), &mut pieces);
+            span.end = line.span(0).end;
+            end = line.end;
+        }
+        // The spaces or the `\` at the end of the paragraph are not a line
+        // break, the `\` is kept as text
+        trim_line_end(self.source, &mut pieces, end);
+
+        if let Some(level) = level {
+            trim_pieces(self.source, &mut pieces);
+            let id = heading_id(self.source, &mut pieces);
+            let content = parse_inline(self.source, pieces, &self.definitions);
+            blocks.push(Block::new(BlockKind::Heading { level, content, id }, span));
+        } else {
+            let content = parse_inline(self.source, pieces, &self.definitions);
+            let span = content_span(&content, span);
+            blocks.push(Block::new(BlockKind::Paragraph(content), span));
+        }
+        blocks.extend(table);
+    }
+
+    /// Adds to the `pieces` the `tokens` of the `line`, parsing its code
+    /// snippets
+    fn line_pieces(&mut self, line: &Line<'a>, tokens: Range<usize>,
+        pieces: &mut Vec<(Piece<'a>, Span)>) {
+        let mut i = tokens.start;
+        while i < tokens.end {
+            let mut span = line.spans[i];
+            let piece = match &line.tokens[i] {
+                Token::ReversedQuote => {
+                    let (code, len) = self.parse_code(line, i);
+                    pieces.push((Piece::Inline(code.clone()), code.span));
+                    i += len;
+                    continue;
+                }
+                Token::Asterisk => Piece::Delimiter('*', 1),
+                Token::LowBar => Piece::Delimiter('_', 1),
+                Token::OpenBracket => Piece::OpenBracket { image: false },
+                Token::CloseBracket => Piece::CloseBracket,
+                Token::Bang if line.tokens.get(i + 1) == Some(&Token::OpenBracket) => {
+                    i += 1;
+                    span.end = line.spans[i].end;
+                    Piece::OpenBracket { image: true }
+                }
+                Token::String(text) => Piece::Text(text.clone()),
+                t => Piece::Text(source_text(self.source, span, &t.literal())),
+            };
+            push_piece(self.source, pieces, piece, span);
+            i += 1;
+        }
+    }
+
+    /// Parses a header, the `#`s followed by its text and optionally by more
+    /// `#`s that close it, the tokenizer only gives the `#`s of a header
+    fn parse_header(&mut self, line: &Line<'a>) -> Block<'a> {
+        let level = line.tokens.iter().take_while(|t| **t == Token::Pad).count();
+
+        // The closing `#`s need an space before them, `# C#` keeps its `#`
+        let blank = |t: &Token| t.literal().trim().is_empty();
+        let mut end = line.tokens.len();
+        while end > level && blank(&line.tokens[end - 1]) {
+            end -= 1;
+        }
+        let pads = line.tokens[level..end].iter().rev().take_while(|t| **t == Token::Pad).count();
+        if end - pads == level || line.tokens[end - pads - 1].literal().ends_with([' ', '\t']) {
+            end -= pads;
+        }
+
+        let mut pieces = Vec::new();
+        self.line_pieces(line, level..end, &mut pieces);
+        trim_line_end(self.source, &mut pieces, line.end);
+        trim_pieces(self.source, &mut pieces);
+        let id = heading_id(self.source, &mut pieces);
+
+        let content = parse_inline(self.source, pieces, &self.definitions);
+        Block::new(BlockKind::Heading { level, content, id }, line.span(0))
+    }
+
+    /// Takes the code snippet at the token `start` of the `line`, the
+    /// ReversedQuotes its code and the closing ReversedQuotes, giving the
+    /// code and the number of tokens it took
+    fn take_code(&mut self, line: &Line<'a>, start: usize) -> (Cow<'a, str>, usize) {
+        let tokens = &line.tokens[start..];
+        let quotes = tokens.iter().take_while(|t| **t == Token::ReversedQuote).count();
+        let (code, mut len) = match tokens.get(quotes) {
+            Some(Token::Code(c)) => (c.clone(), quotes + 1),
+            _ => (Cow::Borrowed(""), quotes),
+        };
+
+        let closing = tokens[len..].iter()
+            .take(quotes)
+            .take_while(|t| **t == Token::ReversedQuote)
+            .count();
+        if closing < quotes {
+            // The tokenizer lets a fence without end last until the end
+            // The error is at the opening quotes
+            let fence = line.spans[start].to(line.spans[start + quotes - 1]);
+            self.errors.push(ParserError::UnclosedCodeFence(fence));
+        }
+        len += closing;
+        (code, len)
+    }
+
+    /// Parses the inline code at the token `start` of the `line`, giving also
+    /// the number of tokens it took
+    fn parse_code(&mut self, line: &Line<'a>, start: usize) -> (Inline<'a>, usize) {
+        let (mut code, len) = self.take_code(line, start);
+
+        // The line breaks are spaces and a space around is taken out
+        if code.contains('\n') {
+            code = Cow::Owned(code.replace('\n', " "));
+        }
+        if code.len() > 2 && code.starts_with(' ') && code.ends_with(' ')
+            && !code.trim().is_empty() {
+            code = part(&code, 1..code.len() - 1);
+        }
+        let span = line.spans[start].to(line.spans[start + len - 1]);
+        (Inline::new(InlineKind::Code(code), span), len)
+    }
+
+    /// Parses the fenced code block at the start of the `line`, 3 or more
+    /// ReversedQuotes with the language, giving also the number of tokens it
+    /// took
+    fn parse_fence(&mut self, line: &Line<'a>) -> (Block<'a>, usize) {
+        let (mut code, len) = self.take_code(line, 0);
 
+        // The lines of a fence inside a list item or indented have the
+        // indentation of the fence, and inside a quote its `>`
+        let indent = line.offset + line.indent;
+        if indent > 0 || line.quotes > 0 {
+            code = Cow::Owned(code.split('\n').enumerate()
+                .map(|(i, l)| if i == 0 { l } else { strip_code_line(l, line.quotes, indent) })
+                .collect::<Vec<_>>()
+                .join("\n"));
+        }
+        let (lang, code) = extract_lang(code);
+        let span = line.spans[0].to(line.spans[len - 1]);
+        (Block::new(BlockKind::CodeBlock { lang, code }, span), len)
+    }
+}
 
+/// Adds to the `definitions` the link definition of the tokens of a `line`,
+/// with their spans in the `source`, if it has one. The first one of a label
+/// is the one used. A definition can't interrupt a paragraph, so there is
+/// none if the line is `after_text`, it gives if the line ends with text that
+/// the next one can continue
+fn add_definition<'a>(definitions: &mut HashMap<String, LinkDefinition<'a>>, source: &'a str,
+    line: &[(Token, Span)], after_text: bool) -> bool {
+    // Also the ones inside the quotes
+    let start = line.iter()
+        .take_while(|(t, _)| matches!(t, Token::Indent(_) | Token::BlockQuote))
+        .count();
+    let line = &line[start..];
+    let tokens: Vec<Token> = line.iter()
+        .map(|(t, _)| t.clone())
+        .skip_while(|t| matches!(t, Token::Bullet(_) | Token::ListNumber(..)
+            | Token::Indent(_) | Token::BlockQuote))
+        .collect();
+    let text = is_text(&tokens);
+    let (Some((Token::OpenBracket, first)), Some((_, last))) = (line.first(), line.last()) else {
+        return text;
+    };
+    if after_text {
+        return text;
+    }
+    let literal: String = line.iter().map(|(t, _)| t.literal()).collect();
+    let def = match source_text(source, first.to(*last), &literal) {
+        Cow::Borrowed(text) => parse_link_definition(text),
+        Cow::Owned(text) => {
+            parse_link_definition(&text).map(|(label, def)| (label, def.into_owned()))
+        }
+    };
+    match def {
+        Some((label, def)) => {
+            definitions.entry(label).or_insert(def);
+            false
+        }
+        None => text,
+    }
+}
+
+/// Splits the info string, the first line of a fenced code, from the code
+/// giving the language it names, its first word
+fn extract_lang(code: Cow<'_, str>) -> (Option<Cow<'_, str>>, Cow<'_, str>) {
+    let (info, start) = match code.find('\n') {
+        Some(end) => (&code[..end], end + 1),
+        None => (&code[..], code.len()),
+    };
+    let lang_start = info.len() - info.trim_start().len();
+    let lang_end = info[lang_start..].find(char::is_whitespace)
+        .map_or(info.len(), |len| lang_start + len);
+    let lang = Some(part(&code, lang_start..lang_end)).filter(|lang| !lang.is_empty());
+
+    // Every line of the code ends with a line break
+    let mut code = part(&code, start..code.len());
+    if !code.is_empty() && !code.ends_with('\n') {
+        code.to_mut().push('\n');
+    }
+    (lang, code)
+}
+
+/// Markdown Parser Iterator that from the Tokens Iterator will yield
+/// the `Block`s of the document. It only looks one line ahead, so the
+/// tokens are taken as they are needed
+pub struct MDParser<'a, I>
+where
+    I: Iterator<Item = (Token<'a>, Span)>,
+{
+    /// The source tokens by lines
+    lines: Peekable<Lines<I>>,
+
+    context: Context<'a>,
+
+    /// Blocks already parsed waiting to be yielded, a line can give more
+    /// than one
+    pending: VecDeque<Block<'a>>,
+}
+
+impl<'a> MDParser<'a, TokenizerIterator<'a>> {
+    /// Instantiate a new `MDParser` that tokenizes the `source` while it's
+    /// parsed, without keeping its tokens. The link definitions can be after
+    /// their links, so they are collected first in another pass, that also
+    /// gives the errors of the tokenizer
+    pub fn new(source: &'a str) -> Self {
+        let mut definitions = HashMap::new();
+        let mut tokens = TokenizerIterator::new(source);
+        let mut line = Vec::new();
+        let mut after_text = false;
+        for (token, span) in tokens.by_ref() {
+            if token == Token::NewLine {
+                after_text = add_definition(&mut definitions, source, &line, after_text);
+                line.clear();
+            } else {
+                line.push((token, span));
+            }
+        }
+        add_definition(&mut definitions, source, &line, after_text);
+
+        MDParser::with_definitions(source, TokenizerIterator::new(source), definitions,
+            tokens.into_errors())
+    }
+}
+
+impl<'a, I> MDParser<'a, I>
+where
+    I: Iterator<Item = (Token<'a>, Span)>,
+{
+    /// The parser of the tokens of the `source`, with the link `definitions`
+    /// of the whole document and the `errors` of the tokenizer
+    fn with_definitions(source: &'a str, tokens: I,
+        definitions: HashMap<String, LinkDefinition<'a>>, errors: Vec<ParserError>) -> Self {
+        MDParser {
+            context: Context { source, errors, definitions, depth: 0 },
+            lines: Lines { source: tokens, end: Position::default() }.peekable(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The errors found until now, the tokenizer ones are found at the start
+    pub fn errors(&self) -> &[ParserError] {
+        &self.context.errors
+    }
+}
+
+/// The main usage of the MDParser, as Iterator
+impl<'a, I> Iterator for MDParser<'a, I>
+where
+    I: Iterator<Item = (Token<'a>, Span)>,
+{
+    type Item = Block<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() {
+            let blocks = self.context.parse_block(&mut self.lines)?;
+            self.pending.extend(blocks);
+        }
+        self.pending.pop_front()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::iter::Zip;
+    use std::vec;
+    use crate::tokenizer::Tokenizer;
+
+    impl<'a> MDParser<'a, Zip<vec::IntoIter<Token<'a>>, vec::IntoIter<Span>>> {
+        /// Instantiate a new `MDParser` given the `Tokenizer` that contains the
+        /// tokens, to check it parses the same as `new`
+        fn from_tokenizer(tokens: Tokenizer<'a>) -> Self {
+            let source = tokens.source();
+            let (tokens, spans, errors) = tokens.into_parts();
+            let mut definitions = HashMap::new();
+            let lines: Vec<(Token, Span)> = tokens.iter().cloned()
+                .zip(spans.iter().copied())
+                .collect();
+            let mut after_text = false;
+            for line in lines.split(|(t, _)| *t == Token::NewLine) {
+                after_text = add_definition(&mut definitions, source, line, after_text);
+            }
+
+            MDParser::with_definitions(source, tokens.into_iter().zip(spans), definitions, errors)
+        }
+    }
+
+    /// The blocks of the `source` without their spans
+    fn parse(source: &str) -> Vec<Block<'_>> {
+        MDParser::new(source).map(Block::without_spans).collect()
+    }
+
+    /// The inlines of a `source` that is a single paragraph
+    fn parse_inline(source: &str) -> Vec<Inline<'_>> {
+        match parse(source).as_slice() {
+            [Block { kind: BlockKind::Paragraph(content), .. }] => content.clone(),
+            blocks => panic!("not a paragraph: {:?}", blocks),
+        }
+    }
+
+    fn block(kind: BlockKind) -> Block {
+        Block::new(kind, Span::default())
+    }
+
+    fn inline(kind: InlineKind) -> Inline {
+        Inline::new(kind, Span::default())
+    }
+
+    fn text(s: &str) -> Inline<'_> {
+        inline(InlineKind::Text(s.into()))
+    }
+
+    fn paragraph(s: &str) -> Block<'_> {
+        block(BlockKind::Paragraph(vec![text(s)]))
+    }
 
     #[test]
     fn parser_tests() {
         // Header tests
-        let tokenizer = Tokenizer::new("# Hello World\n## Hello World\n### Hello World\n#### Hello World\n");
-        let mut parser = MDParser::new(tokenizer); 
-        let mut values = vec![];
-        while let Some(v) = parser.next() {
-            values.push(v);
-        }
-        assert_eq!(vec![
-                   MDValue::BigHeader("Hello World".to_owned()), MDValue::NewLine,
-                   MDValue::MediumHeader("Hello World".to_owned()), MDValue::NewLine,
-                   MDValue::SmallHeader("Hello World".to_owned()), MDValue::NewLine,
-                   MDValue::VerySmallHeader("Hello World".to_owned()), MDValue::NewLine,
-        ], values);
+        let heading = |level| {
+            block(BlockKind::Heading { level, content: vec![text("Hello World")], id: None })
+        };
+        assert_eq!(parse("# Hello World\n## Hello World\n### Hello World\n#### Hello World\n"),
+            vec![heading(1), heading(2), heading(3), heading(4)]);
 
         // Code snippets tests
-        let tokenizer = Tokenizer::new("\n`cargo build --release`
-```rust\nfn main() {\n}\n```\n");
-        let mut parser = MDParser::new(tokenizer); 
-        let mut values = vec![];
-        while let Some(v) = parser.next() {
-            values.push(v);
-        }
-        assert_eq!(vec![
-            MDValue::NewLine,
-            MDValue::CodeSnippet((CSLanguage::Uknown,"cargo build --release".to_owned())), 
-            MDValue::NewLine,
-            MDValue::CodeSnippet((CSLanguage::Rust, "fn main() {\n}\n".to_string())),
-            MDValue::NewLine,
-        ], values);
+        assert_eq!(parse("\n`cargo build --release`\n```rust\nfn main() {\n}\n```\n"), vec![
+            block(BlockKind::Paragraph(vec![
+                inline(InlineKind::Code("cargo build --release".into())),
+            ])),
+            block(BlockKind::CodeBlock {
+                lang: Some("rust".into()),
+                code: "fn main() {\n}\n".into(),
+            }),
+        ]);
 
         // Nothing test
-        let tokenizer = Tokenizer::new("");
-        let mut parser = MDParser::new(tokenizer); 
-        let mut values = vec![];
-        while let Some(v) = parser.next() {
-            values.push(v);
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn emphasis_tests() {
+        let em = |s| inline(InlineKind::Emphasis(vec![text(s)]));
+        let strong = |s| inline(InlineKind::Strong(vec![text(s)]));
+
+        assert_eq!(parse_inline("Some *italic* and **bold** text"), vec![
+            text("Some "), em("italic"), text(" and "), strong("bold"), text(" text"),
+        ]);
+        assert_eq!(parse_inline("_italic_ __bold__ ***both***"), vec![
+            em("italic"), text(" "), strong("bold"), text(" "),
+            inline(InlineKind::Emphasis(vec![strong("both")])),
+        ]);
+        assert_eq!(parse_inline("*a **b** c*"), vec![
+            inline(InlineKind::Emphasis(vec![text("a "), strong("b"), text(" c")])),
+        ]);
+
+        // Delimiters that can't open nor close are just text
+        assert_eq!(parse_inline("snake_case_words"), vec![text("snake_case_words")]);
+        assert_eq!(parse_inline("2 * 3 * 4"), vec![text("2 * 3 * 4")]);
+        assert_eq!(parse_inline("**unclosed *bold"), vec![text("**unclosed *bold")]);
+        assert_eq!(parse_inline("*¡Olé!* y __niño__"), vec![
+            em("¡Olé!"), text(" y "), strong("niño"),
+        ]);
+        assert_eq!(parse_inline("*foo**"), vec![em("foo"), text("*")]);
+    }
+
+    #[test]
+    fn link_tests() {
+        assert_eq!(parse_inline("See [the *repo*](https://github.com/a_b \"Title\")."), vec![
+            text("See "),
+            inline(InlineKind::Link {
+                children: vec![text("the "), inline(InlineKind::Emphasis(vec![text("repo")]))],
+                url: "https://github.com/a_b".into(),
+                title: Some("Title".into()),
+                label: None,
+            }),
+            text("."),
+        ]);
+        assert_eq!(parse_inline("![A *cat*](cat.png)"), vec![
+            inline(InlineKind::Image {
+                alt: "A cat".into(),
+                src: "cat.png".into(),
+                title: None,
+                label: None,
+            }),
+        ]);
+
+        // References can be defined after they are used
+        let link = |text: &'static str, label: &'static str| inline(InlineKind::Link {
+            children: vec![inline(InlineKind::Text(text.into()))],
+            url: "/url".into(),
+            title: Some("T".into()),
+            label: Some(label.into()),
+        });
+        assert_eq!(parse("[full][Id] [id][] [id] ![id]\n\n[ID]: /url 'T'\n"), vec![
+            block(BlockKind::Paragraph(vec![
+                link("full", "Id"),
+                text(" "),
+                link("id", "id"),
+                text(" "),
+                link("id", "id"),
+                text(" "),
+                inline(InlineKind::Image {
+                    alt: "id".into(),
+                    src: "/url".into(),
+                    title: Some("T".into()),
+                    label: Some("id".into()),
+                }),
+            ])),
+        ]);
+
+        // A definition can't interrupt a paragraph, also a lazy one of a list
+        // item, but it can follow other blocks
+        assert_eq!(crate::compile_markdown("foo\n[a]: /url\n\n[a]"),
+            "<p>foo\n[a]: /url</p>\n<p>[a]</p>\n");
+        assert_eq!(crate::compile_markdown("- a\n[q]: /q\n\n[q]"),
+            "<ul>\n<li>a\n[q]: /q</li>\n</ul>\n<p>[q]</p>\n");
+        assert_eq!(crate::compile_markdown("# a\n[a]: /url\n> [a]"),
+            "<h1 id=\"a\">a</h1>\n<blockquote>\n<p><a href=\"/url\">a</a></p>\n</blockquote>\n");
+
+        // Not links
+        assert_eq!(parse_inline("[undefined] and [text] (url) and [a](b"),
+            vec![text("[undefined] and [text] (url) and [a](b")]);
+        assert_eq!(parse_inline("Hello! [[nested](a)](b)"), vec![
+            text("Hello! ["),
+            inline(InlineKind::Link {
+                children: vec![text("nested")],
+                url: "a".into(),
+                title: None,
+                label: None,
+            }),
+            text("](b)"),
+        ]);
+
+        // The labels are at most 999 bytes, and a long paragraph of brackets
+        // is parsed without building its text again for each one
+        let label = "a".repeat(1000);
+        let source = format!("[{}]\n\n[{}]: /u", label, label);
+        assert!(crate::compile_markdown(&source).starts_with("<p>[aaa"));
+        let source = format!("{}[a](b)", "[x] ".repeat(20_000));
+        let content = parse_inline(&source);
+        assert_eq!(content.len(), 2);
+        assert!(matches!(&content[1].kind, InlineKind::Link { url, .. } if url == "b"));
+    }
+
+    #[test]
+    fn header_tests() {
+        let heading = |level, s| {
+            block(BlockKind::Heading { level, content: vec![text(s)], id: None })
+        };
+
+        // All the levels, with closing `#`s that need an space before them
+        assert_eq!(parse("##### five\n###### six ##  \n# C#\n## ###\n#\tTab #"), vec![
+            heading(5, "five"),
+            heading(6, "six"),
+            heading(1, "C#"),
+            block(BlockKind::Heading { level: 2, content: vec![], id: None }),
+            heading(1, "Tab"),
+        ]);
+
+        // The `#`s without an space after them are text
+        assert_eq!(parse("#hashtag\n####### seven"), vec![block(BlockKind::Paragraph(vec![
+            text("#hashtag"), inline(InlineKind::SoftBreak), text("####### seven"),
+        ]))]);
+
+        // Setext headers underline all the lines of the paragraph
+        assert_eq!(parse("Title\n===\nSub *title*\ntwo\n---  \n\n---"), vec![
+            heading(1, "Title"),
+            block(BlockKind::Heading { level: 2, content: vec![
+                text("Sub "),
+                inline(InlineKind::Emphasis(vec![text("title")])),
+                inline(InlineKind::SoftBreak),
+                text("two"),
+            ], id: None }),
+            block(BlockKind::ThematicBreak),
+        ]);
+        // The explicit ids
+        assert_eq!(parse("## Intro {#start}\nSee {#x} {#b-1.2}\n---\n# {#e}\n# a{#b}"), vec![
+            block(BlockKind::Heading {
+                level: 2,
+                content: vec![text("Intro")],
+                id: Some("start".into()),
+            }),
+            block(BlockKind::Heading {
+                level: 2,
+                content: vec![text("See {#x}")],
+                id: Some("b-1.2".into()),
+            }),
+            block(BlockKind::Heading { level: 1, content: vec![], id: Some("e".into()) }),
+            heading(1, "a{#b}"),
+        ]);
+        // Unless the `{` is escaped
+        assert_eq!(parse("# a \\{#b}\n# a \\\\{#b}"), vec![
+            heading(1, "a {#b}"),
+            heading(1, "a \\{#b}"),
+        ]);
+        assert_eq!(crate::compile_markdown("# a \\{#b}"), "<h1 id=\"a-b\">a {#b}</h1>\n");
+        assert_eq!(parse("- a\n  ==\n\n= ="), vec![
+            block(BlockKind::List { start: None, tight: true, items: vec![vec![heading(1, "a")]] }),
+            paragraph("= ="),
+        ]);
+    }
+
+    #[test]
+    fn malformed_input_tests() {
+        let parse = |source| -> (Vec<Block>, Vec<ParserError>) {
+            let mut parser = MDParser::new(source);
+            let blocks = parser.by_ref().map(Block::without_spans).collect();
+            (blocks, parser.errors().to_vec())
+        };
+
+        assert_eq!(parse("####### Too small\n#"), (vec![
+            paragraph("####### Too small"),
+            block(BlockKind::Heading { level: 1, content: vec![], id: None }),
+        ], vec![]));
+        assert_eq!(parse("# A *bold* header"), (vec![block(BlockKind::Heading {
+            level: 1,
+            content: vec![
+                text("A "),
+                inline(InlineKind::Emphasis(vec![text("bold")])),
+                text(" header"),
+            ],
+            id: None,
+        })], vec![]));
+        assert_eq!(parse("`not code"), (vec![paragraph("`not code")], vec![]));
+
+        // The fence without end is still code
+        assert_eq!(parse("text\n```rust\nlet a = 1;\n"), (vec![
+            paragraph("text"),
+            block(BlockKind::CodeBlock {
+                lang: Some("rust".into()),
+                code: "let a = 1;\n".into(),
+            }),
+        ], vec![
+            ParserError::UnclosedCodeFence(Span::new(
+                Position { offset: 5, line: 1, column: 0 },
+                Position { offset: 8, line: 1, column: 3 },
+            )),
+        ]));
+
+        // Too many lists and quotes one inside another, the deeper markers are text
+        let depth = |blocks: &[Block]| {
+            let mut depth = 0;
+            let mut blocks = blocks.to_vec();
+            loop {
+                match blocks.pop().map(|block| block.kind) {
+                    Some(BlockKind::Quote(inner)) => blocks = inner,
+                    Some(BlockKind::List { mut items, .. }) => blocks = items.pop().unwrap(),
+                    _ => return depth,
+                }
+                depth += 1;
+            }
+        };
+        let sources = [
+            ">".repeat(10_000) + "a",
+            "- ".repeat(10_000) + "a",
+            "*a ".repeat(10_000) + &"a* ".repeat(10_000),
+        ];
+        for source in &sources[..2] {
+            assert_eq!(depth(&parse(source).0), MAX_DEPTH);
+        }
+        let inline_depth = |mut inlines: &[Inline]| {
+            let mut depth = 0;
+            while let Some(Inline { kind: InlineKind::Emphasis(children), .. }) = inlines
+                .iter().find(|inline| matches!(inline.kind, InlineKind::Emphasis(_))) {
+                inlines = children;
+                depth += 1;
+            }
+            depth
+        };
+        match &parse(&sources[2]).0[..] {
+            [Block { kind: BlockKind::Paragraph(content), .. }] => {
+                assert_eq!(inline_depth(content), MAX_DEPTH);
+            }
+            blocks => panic!("not a paragraph: {:?}", blocks),
         }
-        assert!(values.is_empty() == true);
+        let stars = "*".repeat(10_000);
+        assert_eq!(crate::compile_markdown(&format!("{}a{}", stars, stars)),
+            format!("<p>{}a{}</p>\n", stars, stars));
+    }
+
+    #[test]
+    fn list_tests() {
+        // Nested lists of mixed types with lazy continuation
+        assert_eq!(parse("- a\n  1. b\n  lazy\n  2. c\n- *d*\ne"), vec![
+            block(BlockKind::List { start: None, tight: true, items: vec![
+                vec![paragraph("a"), block(BlockKind::List {
+                    start: Some(1),
+                    tight: true,
+                    items: vec![
+                        vec![block(BlockKind::Paragraph(vec![
+                            text("b"), inline(InlineKind::SoftBreak), text("lazy"),
+                        ]))],
+                        vec![paragraph("c")],
+                    ],
+                })],
+                vec![block(BlockKind::Paragraph(vec![
+                    inline(InlineKind::Emphasis(vec![text("d")])),
+                    inline(InlineKind::SoftBreak),
+                    text("e"),
+                ]))],
+            ]}),
+        ]);
+
+        // Blank lines between the items make it loose, a different marker
+        // starts another list
+        assert_eq!(parse("3. a\n\n4. b\n- c\n\nd"), vec![
+            block(BlockKind::List {
+                start: Some(3),
+                tight: false,
+                items: vec![vec![paragraph("a")], vec![paragraph("b")]],
+            }),
+            block(BlockKind::List { start: None, tight: true, items: vec![vec![paragraph("c")]] }),
+            paragraph("d"),
+        ]);
+
+        // Also the blank lines between the blocks of an item, but not inside
+        // a nested list
+        assert_eq!(parse("- a\n\n  b\n+ c\n  + d\n\n    e"), vec![
+            block(BlockKind::List { start: None, tight: false, items: vec![
+                vec![paragraph("a"), paragraph("b")],
+            ]}),
+            block(BlockKind::List { start: None, tight: true, items: vec![
+                vec![paragraph("c"), block(BlockKind::List {
+                    start: None,
+                    tight: false,
+                    items: vec![vec![paragraph("d"), paragraph("e")]],
+                })],
+            ]}),
+        ]);
+
+        // The code fence inside an item without its indentation
+        assert_eq!(parse("1. code:\n   ```c\n   int a;\n   ```"), vec![
+            block(BlockKind::List { start: Some(1), tight: true, items: vec![vec![
+                paragraph("code:"),
+                block(BlockKind::CodeBlock {
+                    lang: Some("c".into()),
+                    code: "int a;\n".into(),
+                }),
+            ]]}),
+        ]);
+    }
+
+    #[test]
+    fn paragraph_tests() {
+        // The lines of a paragraph keep their soft line breaks, 2 spaces or a
+        // `\` at the end give a hard one, the blank lines split them
+        assert_eq!(parse("a  \nb\\\n  *c\nd*  \n\ne"), vec![
+            block(BlockKind::Paragraph(vec![
+                text("a"), inline(InlineKind::HardBreak),
+                text("b"), inline(InlineKind::HardBreak),
+                inline(InlineKind::Emphasis(vec![
+                    text("c"), inline(InlineKind::SoftBreak), text("d"),
+                ])),
+            ])),
+            paragraph("e"),
+        ]);
+
+        // An escaped `\` is just text
+        assert_eq!(parse("a\\\\\nb\n\na\\\\\\\\\nb\n\na\\\\\\\nb"), vec![
+            block(BlockKind::Paragraph(vec![
+                text("a\\"), inline(InlineKind::SoftBreak), text("b"),
+            ])),
+            block(BlockKind::Paragraph(vec![
+                text("a\\\\"), inline(InlineKind::SoftBreak), text("b"),
+            ])),
+            block(BlockKind::Paragraph(vec![
+                text("a\\"), inline(InlineKind::HardBreak), text("b"),
+            ])),
+        ]);
+        assert_eq!(crate::compile_markdown("a\\\\\nb"), "<p>a\\\nb</p>\n");
+        assert_eq!(crate::compile_markdown("a\\\\\\\\\nb"), "<p>a\\\\\nb</p>\n");
+
+        // At the end of a block the `\` is just text
+        assert_eq!(parse("a\\
+
+# b \\  
+c\\  "), vec![
+            paragraph("a\\"),
+            block(BlockKind::Heading { level: 1, content: vec![text("b \\")], id: None }),
+            paragraph("c\\"),
+        ]);
+        assert_eq!(crate::compile_markdown("a\\"), "<p>a\\</p>\n");
+
+        // Only a non empty list starting at 1 or a header interrupt it
+        assert_eq!(parse("a\n2. b\n*\n# c\n1. d"), vec![
+            block(BlockKind::Paragraph(vec![
+                text("a"), inline(InlineKind::SoftBreak),
+                text("2. b"), inline(InlineKind::SoftBreak),
+                text("*"),
+            ])),
+            block(BlockKind::Heading { level: 1, content: vec![text("c")], id: None }),
+            block(BlockKind::List {
+                start: Some(1),
+                tight: true,
+                items: vec![vec![paragraph("d")]],
+            }),
+        ]);
+    }
+
+    #[test]
+    fn code_tests() {
+        // Inline code, also with 3 quotes inside a line
+        assert_eq!(parse_inline("`` a`b `` and ```c\nd```"), vec![
+            inline(InlineKind::Code("a`b".into())),
+            text(" and "),
+            inline(InlineKind::Code("c d".into())),
+        ]);
+
+        // The info string is not part of the code even if it's unknown
+        assert_eq!(parse("```python x\nprint()\n```\n```\n<none>"), vec![
+            block(BlockKind::CodeBlock {
+                lang: Some("python".into()),
+                code: "print()\n".into(),
+            }),
+            block(BlockKind::CodeBlock { lang: None, code: "<none>\n".into() }),
+        ]);
+    }
+
+    #[test]
+    fn quote_tests() {
+        let quote = |blocks| block(BlockKind::Quote(blocks));
+
+        // Nested quotes with lazy continuation of the innermost paragraph,
+        // a `>` alone is a blank line inside the quote
+        assert_eq!(parse("> a\n> > b\nc\n>\n> d\n\n> e"), vec![
+            quote(vec![
+                paragraph("a"),
+                quote(vec![block(BlockKind::Paragraph(vec![
+                    text("b"), inline(InlineKind::SoftBreak), text("c"),
+                ]))]),
+                paragraph("d"),
+            ]),
+            quote(vec![paragraph("e")]),
+        ]);
+
+        // Any block inside, and only the text can be lazy
+        assert_eq!(parse("> # h\n> - x\n>   ```rust\n>   let a;\n>   ```\n- y"), vec![
+            quote(vec![
+                block(BlockKind::Heading { level: 1, content: vec![text("h")], id: None }),
+                block(BlockKind::List { start: None, tight: true, items: vec![vec![
+                    paragraph("x"),
+                    block(BlockKind::CodeBlock {
+                        lang: Some("rust".into()),
+                        code: "let a;\n".into(),
+                    }),
+                ]]}),
+            ]),
+            block(BlockKind::List { start: None, tight: true, items: vec![vec![paragraph("y")]] }),
+        ]);
+        assert_eq!(parse("a\n> b\n# c"), vec![
+            paragraph("a"),
+            quote(vec![paragraph("b")]),
+            block(BlockKind::Heading { level: 1, content: vec![text("c")], id: None }),
+        ]);
+
+        // The quote in a list item and the definitions in a quote
+        assert_eq!(parse("- > [a]\n  >\n  > [a]: /u\n\n  b"), vec![
+            block(BlockKind::List { start: None, tight: false, items: vec![vec![
+                quote(vec![block(BlockKind::Paragraph(vec![
+                    inline(InlineKind::Link {
+                        children: vec![text("a")],
+                        url: "/u".into(),
+                        title: None,
+                        label: Some("a".into()),
+                    }),
+                ]))]),
+                paragraph("b"),
+            ]]}),
+        ]);
+    }
+
+    #[test]
+    fn table_tests() {
+        let cell = |s: &'static str| TableCell {
+            content: if s.is_empty() { vec![] } else { vec![text(s)] },
+            span: Span::default(),
+        };
+        let row = |cells: &[&'static str]| cells.iter().map(|s| cell(s)).collect::<Vec<_>>();
+
+        // The alignments and the ragged rows, the missing cells are empty and
+        // the extra ones are dropped
+        assert_eq!(parse("| a | b | c |\n|---|:--|:-:|\n| 1 | 2 |\n|3|4|5|6|\n7\n\nd"), vec![
+            block(BlockKind::Table {
+                alignments: vec![Alignment::None, Alignment::Left, Alignment::Center],
+                header: row(&["a", "b", "c"]),
+                rows: vec![row(&["1", "2", ""]), row(&["3", "4", "5"]), row(&["7", "", ""])],
+            }),
+            paragraph("d"),
+        ]);
+
+        // Without the outer pipes, with the pipes escaped or in code, and
+        // with inlines in the cells
+        assert_eq!(parse("x | *y*\n--: | -\n`|` \\| z | w"), vec![
+            block(BlockKind::Table {
+                alignments: vec![Alignment::Right, Alignment::None],
+                header: vec![cell("x"), TableCell {
+                    content: vec![inline(InlineKind::Emphasis(vec![text("y")]))],
+                    span: Span::default(),
+                }],
+                rows: vec![vec![
+                    TableCell {
+                        content: vec![inline(InlineKind::Code("|".into())), text(" | z")],
+                        span: Span::default(),
+                    },
+                    cell("w"),
+                ]],
+            }),
+        ]);
+
+        // A table interrupts a paragraph, the pipes of the code don't split
+        // the cells
+        assert_eq!(parse("p\n| `a|b` | c |\n|-|-|\n| ``d|`|e`` |"), vec![
+            paragraph("p"),
+            block(BlockKind::Table {
+                alignments: vec![Alignment::None, Alignment::None],
+                header: vec![TableCell {
+                    content: vec![inline(InlineKind::Code("a|b".into()))],
+                    span: Span::default(),
+                }, cell("c")],
+                rows: vec![vec![TableCell {
+                    content: vec![inline(InlineKind::Code("d|`|e".into()))],
+                    span: Span::default(),
+                }, cell("")]],
+            }),
+        ]);
+
+        // Not tables, the delimiter row must match the header
+        assert_eq!(parse("a | b\n--- | --- | ---"), vec![block(BlockKind::Paragraph(vec![
+            text("a | b"), inline(InlineKind::SoftBreak), text("--- | --- | ---"),
+        ]))]);
+        assert_eq!(parse("a | b\nc | d"), vec![block(BlockKind::Paragraph(vec![
+            text("a | b"), inline(InlineKind::SoftBreak), text("c | d"),
+        ]))]);
+    }
+
+    #[test]
+    fn thematic_break_tests() {
+        let hr = || block(BlockKind::ThematicBreak);
+        let list = |items: Vec<Vec<Block<'static>>>| {
+            block(BlockKind::List { start: None, tight: true, items })
+        };
+
+        // Any of the marks, with spaces, interrupting a paragraph
+        assert_eq!(parse("***\n- - -\n _ _ _ \na\n*  *  *\n-----"), vec![
+            hr(), hr(), hr(), paragraph("a"), hr(), hr(),
+        ]);
+
+        // They end the lists, also with the marker of the list, and can be
+        // inside an item
+        assert_eq!(parse("* a\n* * *\n- b\n___\n- ***"), vec![
+            list(vec![vec![paragraph("a")]]),
+            hr(),
+            list(vec![vec![paragraph("b")]]),
+            hr(),
+            list(vec![vec![hr()]]),
+        ]);
+        assert_eq!(parse("> a\n---"), vec![block(BlockKind::Quote(vec![paragraph("a")])), hr()]);
+
+        // Not breaks, the emphasis, the escaped marks, mixed marks or less
+        // than 3
+        assert_eq!(parse("***a***\n\n\\*\\*\\*\n\n*-*\n\n--"), vec![
+            block(BlockKind::Paragraph(vec![inline(InlineKind::Emphasis(vec![
+                inline(InlineKind::Strong(vec![text("a")])),
+            ]))])),
+            paragraph("***"),
+            block(BlockKind::Paragraph(vec![inline(InlineKind::Emphasis(vec![text("-")]))])),
+            paragraph("--"),
+        ]);
+    }
+
+    #[test]
+    fn toc_marker_tests() {
+        let toc = || block(BlockKind::TableOfContents);
+        assert_eq!(parse("[TOC]\n# a\n\n- [[toc]]  \n\n[toc]\ntext"), vec![
+            toc(),
+            block(BlockKind::Heading { level: 1, content: vec![text("a")], id: None }),
+            block(BlockKind::List { start: None, tight: true, items: vec![vec![toc()]] }),
+            block(BlockKind::Paragraph(vec![
+                text("[toc]"), inline(InlineKind::SoftBreak), text("text"),
+            ])),
+        ]);
+
+        // A link with its definition
+        assert_eq!(parse("[TOC]\n\n[toc]: /toc"), vec![
+            block(BlockKind::Paragraph(vec![inline(InlineKind::Link {
+                children: vec![text("TOC")],
+                url: "/toc".into(),
+                title: None,
+                label: Some("TOC".into()),
+            })])),
+        ]);
+    }
+
+    #[test]
+    fn streaming_tests() {
+        // The same blocks and errors tokenizing while parsing or before, also
+        // with the definitions after their links
+        let source = "[a]\n\n- b\0\n\n[a]: /u\n> ```\n> c";
+        let mut streaming = MDParser::new(source);
+        let mut eager = MDParser::from_tokenizer(Tokenizer::new(source));
+        let blocks: Vec<Block> = streaming.by_ref().collect();
+        assert_eq!(blocks, eager.by_ref().collect::<Vec<_>>());
+        assert_eq!(streaming.errors(), eager.errors());
+        assert_eq!(streaming.errors().len(), 2);
+        assert!(matches!(&blocks[0].kind, BlockKind::Paragraph(content)
+            if matches!(&content[0].kind, InlineKind::Link { url, .. } if url == "/u")));
+    }
+
+    #[test]
+    fn span_tests() {
+        let source = "- *ñ* [a](b \"t\") `c`  \n  **dé**\n\n```\nx\n```";
+        let blocks: Vec<Block> = MDParser::new(source).collect();
+        let slice = |span: Span| &source[span.range()];
+
+        let (list, code) = match blocks.as_slice() {
+            [list, code] => (list, code),
+            blocks => panic!("{:?}", blocks),
+        };
+        assert_eq!(slice(list.span), "- *ñ* [a](b \"t\") `c`  \n  **dé**");
+        assert_eq!(slice(code.span), "```\nx\n```");
+        assert_eq!(code.span.start, Position { offset: 35, line: 3, column: 0 });
+
+        let content = match &list.kind {
+            BlockKind::List { items, .. } => match &items[0][0].kind {
+                BlockKind::Paragraph(content) => content,
+                kind => panic!("{:?}", kind),
+            },
+            kind => panic!("{:?}", kind),
+        };
+        let slices: Vec<&str> = content.iter().map(|inline| slice(inline.span)).collect();
+        assert_eq!(slices, vec!["*ñ*", " ", "[a](b \"t\")", " ", "`c`", "  \n", "**dé**"]);
+        assert_eq!(content[6].span.start, Position { offset: 26, line: 1, column: 2 });
+
+        // The tabs at the end of a line are one char in the source but are
+        // taken as spaces, the spans of the trimmed text are still in it
+        let sources = ["a\n+\t", "x\n    1.\t\t", "a \t\nb", "# a\t{#b}\t", "| a\t|\n|-|\n|\tb\t|"];
+        for source in sources {
+            let blocks: Vec<Block> = MDParser::new(source).collect();
+            let mut spans = Vec::new();
+            for block in &blocks {
+                spans.push(block.span);
+                match &block.kind {
+                    BlockKind::Paragraph(content) | BlockKind::Heading { content, .. } => {
+                        spans.extend(content.iter().map(|inline| inline.span));
+                    }
+                    BlockKind::Table { header, rows, .. } => {
+                        let cells = header.iter().chain(rows.iter().flatten());
+                        spans.extend(cells.flat_map(|cell| &cell.content).map(|i| i.span));
+                    }
+                    kind => panic!("{:?}", kind),
+                }
+            }
+            for span in spans {
+                assert!(span.start <= span.end && span.end.offset <= source.len(),
+                    "{:?} {:?}", source, span);
+                assert_eq!(span.end, span.start.advance(&source[span.range()]), "{:?}", source);
+            }
+        }
+        assert_eq!(Position { offset: 1, line: 0, column: 1 }.back(4), Position::default());
+    }
+
+    #[test]
+    fn borrow_tests() {
+        let borrowed = |text: &Cow<str>| matches!(text, Cow::Borrowed(_));
+        let source = "# Title *a* {#id}\nSome text\nin [two](/u \"t\") lines  \nand `code` \\*\n\n\
+            ```rust\nfn main() {}\n```\n\n[def]: /d";
+        let blocks: Vec<Block> = MDParser::new(source).collect();
+        let (heading, paragraph, code) = match blocks.as_slice() {
+            [Block { kind: BlockKind::Heading { content, id: Some(id), .. }, .. },
+                Block { kind: BlockKind::Paragraph(paragraph), .. },
+                Block { kind: BlockKind::CodeBlock { lang: Some(lang), code }, .. }] => {
+                assert!(borrowed(id) && borrowed(lang) && borrowed(code));
+                (content, paragraph, code)
+            }
+            blocks => panic!("{:?}", blocks),
+        };
+        assert!(matches!(&heading[0].kind, InlineKind::Text(text) if borrowed(text)));
+        assert_eq!(code, "fn main() {}\n");
+
+        // The lines are joined and only the escaped text is copied
+        let texts: Vec<(String, bool)> = paragraph.iter()
+            .filter_map(|inline| match &inline.kind {
+                InlineKind::Text(text) | InlineKind::Code(text) => {
+                    Some((text.to_string(), borrowed(text)))
+                }
+                InlineKind::Link { url, title: Some(title), .. } => {
+                    assert!(borrowed(url) && borrowed(title));
+                    None
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, vec![
+            ("Some text".to_owned(), true), ("in ".to_owned(), true), (" lines".to_owned(), true),
+            ("and ".to_owned(), true), ("code".to_owned(), true), (" *".to_owned(), false),
+        ]);
+
+        // An owned document doesn't need the source
+        let owned: Vec<Block<'static>> = {
+            let source = source.to_owned();
+            MDParser::new(&source).map(Block::into_owned).collect()
+        };
+        assert_eq!(owned, blocks);
     }
 }
@@ -0,0 +1,324 @@
+//! The rendering of a document node by node, a `Renderer` has a hook for
+//! each kind of node that gives its html by default, so another output only
+//! overrides the hooks it changes
+
+use std::fmt;
+
+use super::ast::{Alignment, Block, BlockKind, Inline, InlineKind, TableCell};
+use super::compiler::HtmlOptions;
+use super::highlight::Highlighters;
+use super::html;
+use super::toc::{self, TocEntry};
+
+/// The hooks called for each node of a document as it's rendered, they write
+/// into the `ctx`. The default of each one is the function with its name in
+/// the `html` module, so a hook can also wrap the default html, like
+///
+/// ```
+/// use std::fmt::{self, Write};
+/// use markdown_compiler::*;
+///
+/// struct Figures;
+///
+/// impl Renderer for Figures {
+///     fn code_block(&mut self, ctx: &mut RenderContext, lang: Option<&str>, code: &str)
+///         -> fmt::Result {
+///         ctx.write_str("<figure>")?;
+///         html::code_block(self, ctx, lang, code)?;
+///         ctx.write_str("</figure>")
+///     }
+/// }
+///
+/// let mut out = String::new();
+/// render_to_fmt_with_renderer(&parse_markdown("```\na\n```"), &mut out, &mut Figures,
+///     &Highlighters::default(), &HtmlOptions::default())?;
+/// assert_eq!(out, "<figure><pre><code>a\n</code></pre></figure>\n");
+/// # Ok::<(), fmt::Error>(())
+/// ```
+///
+/// The children of a node are rendered calling the hooks again, so they get
+/// the overridden hooks too
+pub trait Renderer {
+    /// The blocks of the whole document
+    fn document(&mut self, ctx: &mut RenderContext, blocks: &[Block]) -> fmt::Result {
+        html::document(self, ctx, blocks)
+    }
+
+    /// Calls the hook of the kind of the `block`. The headings get their id
+    /// here, so this shouldn't be overridden without calling it
+    fn block(&mut self, ctx: &mut RenderContext, block: &Block) -> fmt::Result {
+        match &block.kind {
+            BlockKind::Heading { level, content, id } => {
+                let id = ctx.heading_id(id.as_deref());
+                self.heading(ctx, *level, content, id.as_deref())
+            }
+            BlockKind::Paragraph(content) => self.paragraph(ctx, content),
+            BlockKind::CodeBlock { lang, code } => self.code_block(ctx, lang.as_deref(), code),
+            BlockKind::List { start, tight, items } => self.list(ctx, *start, *tight, items),
+            BlockKind::Quote(blocks) => self.quote(ctx, blocks),
+            BlockKind::Table { alignments, header, rows } => {
+                self.table(ctx, alignments, header, rows)
+            }
+            BlockKind::ThematicBreak => self.thematic_break(ctx),
+            BlockKind::TableOfContents => self.table_of_contents(ctx),
+        }
+    }
+
+    /// A heading with the `id` it has in the html, if any
+    fn heading(&mut self, ctx: &mut RenderContext, level: usize, content: &[Inline],
+        id: Option<&str>) -> fmt::Result {
+        html::heading(self, ctx, level, content, id)
+    }
+
+    fn paragraph(&mut self, ctx: &mut RenderContext, content: &[Inline]) -> fmt::Result {
+        html::paragraph(self, ctx, content)
+    }
+
+    fn code_block(&mut self, ctx: &mut RenderContext, lang: Option<&str>, code: &str)
+        -> fmt::Result {
+        html::code_block(self, ctx, lang, code)
+    }
+
+    fn list(&mut self, ctx: &mut RenderContext, start: Option<usize>, tight: bool,
+        items: &[Vec<Block>]) -> fmt::Result {
+        html::list(self, ctx, start, tight, items)
+    }
+
+    fn list_item(&mut self, ctx: &mut RenderContext, blocks: &[Block], tight: bool)
+        -> fmt::Result {
+        html::list_item(self, ctx, blocks, tight)
+    }
+
+    fn quote(&mut self, ctx: &mut RenderContext, blocks: &[Block]) -> fmt::Result {
+        html::quote(self, ctx, blocks)
+    }
+
+    fn table(&mut self, ctx: &mut RenderContext, alignments: &[Alignment], header: &[TableCell],
+        rows: &[Vec<TableCell>]) -> fmt::Result {
+        html::table(self, ctx, alignments, header, rows)
+    }
+
+    /// A row of a table, the `header` one or one of the body
+    fn table_row(&mut self, ctx: &mut RenderContext, cells: &[TableCell],
+        alignments: &[Alignment], header: bool) -> fmt::Result {
+        html::table_row(self, ctx, cells, alignments, header)
+    }
+
+    fn thematic_break(&mut self, ctx: &mut RenderContext) -> fmt::Result {
+        html::thematic_break(self, ctx)
+    }
+
+    /// A `[TOC]` marker, the headings are in `ctx.outline()`
+    fn table_of_contents(&mut self, ctx: &mut RenderContext) -> fmt::Result {
+        html::table_of_contents(self, ctx)
+    }
+
+    fn inlines(&mut self, ctx: &mut RenderContext, inlines: &[Inline]) -> fmt::Result {
+        inlines.iter().try_for_each(|inline| self.inline(ctx, inline))
+    }
+
+    /// Calls the hook of the kind of the `inline`
+    fn inline(&mut self, ctx: &mut RenderContext, inline: &Inline) -> fmt::Result {
+        match &inline.kind {
+            InlineKind::Text(text) => self.text(ctx, text),
+            InlineKind::Code(code) => self.code(ctx, code),
+            InlineKind::Emphasis(children) => self.emphasis(ctx, children),
+            InlineKind::Strong(children) => self.strong(ctx, children),
+            InlineKind::Link { children, url, title, .. } => {
+                self.link(ctx, children, url, title.as_deref())
+            }
+            InlineKind::Image { alt, src, title, .. } => {
+                self.image(ctx, alt, src, title.as_deref())
+            }
+            InlineKind::SoftBreak => self.soft_break(ctx),
+            InlineKind::HardBreak => self.hard_break(ctx),
+        }
+    }
+
+    fn text(&mut self, ctx: &mut RenderContext, text: &str) -> fmt::Result {
+        html::text(self, ctx, text)
+    }
+
+    fn code(&mut self, ctx: &mut RenderContext, code: &str) -> fmt::Result {
+        html::code(self, ctx, code)
+    }
+
+    fn emphasis(&mut self, ctx: &mut RenderContext, children: &[Inline]) -> fmt::Result {
+        html::emphasis(self, ctx, children)
+    }
+
+    fn strong(&mut self, ctx: &mut RenderContext, children: &[Inline]) -> fmt::Result {
+        html::strong(self, ctx, children)
+    }
+
+    fn link(&mut self, ctx: &mut RenderContext, children: &[Inline], url: &str,
+        title: Option<&str>) -> fmt::Result {
+        html::link(self, ctx, children, url, title)
+    }
+
+    fn image(&mut self, ctx: &mut RenderContext, alt: &str, src: &str, title: Option<&str>)
+        -> fmt::Result {
+        html::image(self, ctx, alt, src, title)
+    }
+
+    fn soft_break(&mut self, ctx: &mut RenderContext) -> fmt::Result {
+        html::soft_break(self, ctx)
+    }
+
+    fn hard_break(&mut self, ctx: &mut RenderContext) -> fmt::Result {
+        html::hard_break(self, ctx)
+    }
+}
+
+/// The renderer of the html, with every hook as its default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HtmlRenderer;
+
+impl Renderer for HtmlRenderer {}
+
+/// Where the hooks write, with the state of the rendering of a document.
+/// The headings take their ids from its outline, so they are unique and the
+/// same of the table of contents
+pub struct RenderContext<'a> {
+    out: &'a mut dyn fmt::Write,
+    highlighters: &'a Highlighters,
+    options: &'a HtmlOptions,
+    outline: Vec<TocEntry>,
+
+    /// If the headings get the slugs as ids, also when the options don't
+    /// want them but the table of contents links to them
+    slug_ids: bool,
+
+    /// How many headings were rendered
+    headings: usize,
+}
+
+impl<'a> RenderContext<'a> {
+    /// The context to render the `blocks` of a document into `out`
+    pub(crate) fn new(out: &'a mut dyn fmt::Write, blocks: &[Block],
+        highlighters: &'a Highlighters, options: &'a HtmlOptions) -> Self {
+        RenderContext {
+            out,
+            highlighters,
+            options,
+            outline: toc::outline(blocks),
+            slug_ids: options.heading_ids || toc::has_toc(blocks),
+            headings: 0,
+        }
+    }
+
+    pub fn highlighters(&self) -> &'a Highlighters {
+        self.highlighters
+    }
+
+    pub fn options(&self) -> &'a HtmlOptions {
+        self.options
+    }
+
+    /// The headings of the document, see `Document::outline`
+    pub fn outline(&self) -> &[TocEntry] {
+        &self.outline
+    }
+
+    /// Writes the table of contents of the outline, with the levels of the
+    /// options
+    pub(crate) fn write_toc(&mut self) -> fmt::Result {
+        toc::write_toc(self.out, &self.outline, &self.options.toc)
+    }
+
+    /// The id of the next heading, its `explicit` one or its slug if the
+    /// options want it or there is a table of contents
+    fn heading_id(&mut self, explicit: Option<&str>) -> Option<String> {
+        let slug = self.outline.get(self.headings).map(|entry| entry.slug.clone());
+        self.headings += 1;
+        match explicit {
+            Some(id) => Some(id.to_owned()),
+            None if self.slug_ids => slug,
+            None => None,
+        }
+    }
+}
+
+impl fmt::Write for RenderContext<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_str(s)
+    }
+
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        self.out.write_char(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+    use crate::html::{EscapeHtml, EscapeUrl};
+    use crate::{parse_markdown, render_to_fmt_with_renderer};
+
+    fn render<R: Renderer>(renderer: &mut R, source: &str) -> String {
+        let mut out = String::new();
+        render_to_fmt_with_renderer(&parse_markdown(source), &mut out, renderer,
+            &Highlighters::default(), &HtmlOptions::default()).unwrap();
+        out
+    }
+
+    #[test]
+    fn renderer_tests() {
+        // The default hooks give the same html
+        let source = "# A\n[[toc]]\n- *b* [c](/c \"t\")\n\n```rs\n1\n```\n\n| d |\n|:-|\n";
+        assert_eq!(render(&mut HtmlRenderer, source), crate::compile_markdown(source));
+
+        // Overriding a hook, also for the nodes inside others
+        struct LazyImages;
+        impl Renderer for LazyImages {
+            fn image(&mut self, ctx: &mut RenderContext, alt: &str, src: &str, _: Option<&str>)
+                -> fmt::Result {
+                write!(ctx, "<img loading=\"lazy\" src=\"{}\" alt=\"{}\">", EscapeUrl(src),
+                    EscapeHtml(alt))
+            }
+        }
+        assert_eq!(render(&mut LazyImages, "> *![a](a.png)*"),
+            "<blockquote>\n<p><em><img loading=\"lazy\" src=\"a.png\" alt=\"a\"></em></p>\n\
+            </blockquote>\n");
+
+        // Wrapping the default html, with state in the renderer
+        struct Figures(usize);
+        impl Renderer for Figures {
+            fn code_block(&mut self, ctx: &mut RenderContext, lang: Option<&str>, code: &str)
+                -> fmt::Result {
+                self.0 += 1;
+                write!(ctx, "<figure id=\"code-{}\">", self.0)?;
+                html::code_block(self, ctx, lang, code)?;
+                ctx.write_str("</figure>")
+            }
+        }
+        assert_eq!(render(&mut Figures(0), "```\na\n```\n- ```\n  b\n  ```"),
+            "<figure id=\"code-1\"><pre><code>a\n</code></pre></figure>\n<ul>\n<li>\n\
+            <figure id=\"code-2\"><pre><code>b\n</code></pre></figure>\n</li>\n</ul>\n");
+
+        // The headings keep their ids even if their hook doesn't use them
+        struct Plain;
+        impl Renderer for Plain {
+            fn heading(&mut self, ctx: &mut RenderContext, level: usize, content: &[Inline],
+                _: Option<&str>) -> fmt::Result {
+                write!(ctx, "<h{}>", level)?;
+                self.inlines(ctx, content)?;
+                write!(ctx, "</h{}>", level)
+            }
+        }
+        assert_eq!(render(&mut Plain, "# a\n> # b\n\n## a"),
+            "<h1>a</h1>\n<blockquote>\n<h1>b</h1>\n</blockquote>\n<h2>a</h2>\n");
+        struct Last;
+        impl Renderer for Last {
+            fn heading(&mut self, ctx: &mut RenderContext, level: usize, content: &[Inline],
+                id: Option<&str>) -> fmt::Result {
+                if level == 1 {
+                    return Ok(());
+                }
+                html::heading(self, ctx, level, content, id)
+            }
+        }
+        assert_eq!(render(&mut Last, "# a\n## a"), "\n<h2 id=\"a-1\">a</h2>\n");
+    }
+}
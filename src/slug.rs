@@ -0,0 +1,82 @@
+//! The ids of the headings, made from their text like GitHub does, so the
+//! links to the sections of a README keep working
+
+use std::collections::HashMap;
+
+/// If the `ch` is a combining mark, like the accent of `e\u{301}`, the ranges
+/// of the combining diacritical marks blocks
+fn is_mark(ch: char) -> bool {
+    matches!(ch, '\u{300}'..='\u{36F}' | '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}' | '\u{FE20}'..='\u{FE2F}')
+}
+
+/// The slug of the `text` of a heading as GitHub makes it, in lowercase, the
+/// spaces are `-` and without the punctuation nor the symbols, but the
+/// letters and the numbers of any script are kept
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter(|ch| ch.is_alphanumeric() || is_mark(*ch) || matches!(ch, ' ' | '-' | '_'))
+        .map(|ch| if ch == ' ' { '-' } else { ch })
+        .collect()
+}
+
+/// Gives the unique ids of the headings of a document, the repeated slugs
+/// get a `-1`, `-2`... suffix
+#[derive(Debug, Clone, Default)]
+pub struct Slugger {
+    /// The ids already given, with the last suffix of the slugs that were
+    /// repeated
+    seen: HashMap<String, usize>,
+}
+
+impl Slugger {
+    pub fn new() -> Self {
+        Slugger::default()
+    }
+
+    /// The unique id of a heading with the `text`, a `section` one if its
+    /// slug is empty, like the one of an emoji, so it can still be linked
+    pub fn slug(&mut self, text: &str) -> String {
+        let slug = match slugify(text) {
+            slug if slug.is_empty() => "section".to_owned(),
+            slug => slug,
+        };
+        let mut id = slug.clone();
+        while self.seen.contains_key(&id) {
+            let count = self.seen.entry(slug.clone()).or_insert(0);
+            *count += 1;
+            id = format!("{}-{}", slug, count);
+        }
+        self.seen.insert(id.clone(), 0);
+        id
+    }
+
+    /// Takes the explicit `id` of a heading as given, so no slug repeats it
+    pub fn add(&mut self, id: &str) {
+        self.seen.entry(id.to_owned()).or_insert(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slug_tests() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  What's `new` in v1.2?"), "--whats-new-in-v12");
+        assert_eq!(slugify("snake_case & kebab-case"), "snake_case--kebab-case");
+        assert_eq!(slugify("Ünïcode Straße 日本語 🎉"), "ünïcode-straße-日本語-");
+        assert_eq!(slugify("Cafe\u{301}"), "cafe\u{301}");
+
+        // The repeated ones, also with the suffix of another one
+        let mut slugger = Slugger::new();
+        slugger.add("intro");
+        let ids: Vec<String> = ["Intro", "Intro", "Intro 1", "Intro", "Usage", ""].iter()
+            .map(|text| slugger.slug(text))
+            .collect();
+        assert_eq!(ids, vec!["intro-1", "intro-2", "intro-1-1", "intro-3", "usage", "section"]);
+        assert_eq!(slugger.slug("🎉"), "section-1");
+    }
+}
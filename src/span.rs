@@ -0,0 +1,107 @@
+//! The locations in the source of the tokens, the nodes of the document and
+//! the errors
+
+use std::fmt;
+
+/// A position in the source, between two chars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Position {
+    /// Byte offset from the start of the source
+    pub offset: usize,
+
+    /// Line, starting at 0, the `\n`, `\r\n` and `\r` end the lines
+    pub line: usize,
+
+    /// Chars from the start of the line, starting at 0, so a multi-byte char
+    /// or a tab is one column
+    pub column: usize,
+}
+
+impl Position {
+    /// The position after the `text` that starts at this one
+    pub(crate) fn advance(self, text: &str) -> Position {
+        let mut position = Position { offset: self.offset + text.len(), ..self };
+        for ch in text.chars() {
+            if ch == '\n' {
+                position.line += 1;
+                position.column = 0;
+            } else {
+                position.column += 1;
+            }
+        }
+        position
+    }
+
+    /// The position `n` chars after this one in the same line, the chars
+    /// must be one byte long
+    pub(crate) fn forward(self, n: usize) -> Position {
+        Position { offset: self.offset + n, column: self.column + n, ..self }
+    }
+
+    /// The position `n` chars before this one in the same line, the chars
+    /// must be one byte long. It never goes before the start of the line
+    pub(crate) fn back(self, n: usize) -> Position {
+        let n = n.min(self.column).min(self.offset);
+        Position { offset: self.offset - n, column: self.column - n, ..self }
+    }
+}
+
+/// Shown as `line:column`, both starting at 1
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
+
+/// The part of the source from `start` until `end`, without it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+
+    /// An empty span at the `position`
+    pub fn at(position: Position) -> Self {
+        Span { start: position, end: position }
+    }
+
+    /// The span from the start of this one until the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.offset == self.end.offset
+    }
+
+    /// The byte range of the span, to slice the source
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.start.offset..self.end.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn span_tests() {
+        let source = "día\n\tb";
+        let start = Position { offset: 0, line: 0, column: 0 };
+        let end = Position { offset: 4, line: 0, column: 3 };
+        let span = Span::new(start, end);
+        assert_eq!(&source[span.range()], "día");
+        assert_eq!(end.to_string(), "1:4");
+
+        let b = Span::at(Position { offset: 6, line: 1, column: 1 });
+        assert!(b.is_empty());
+        assert_eq!(&source[span.to(b).range()], "día\n\t");
+        assert_eq!(b.start.forward(1).back(1), b.start);
+        assert_eq!(start.advance("día\n\t"), b.start);
+    }
+}
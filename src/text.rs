@@ -0,0 +1,384 @@
+//! The plain text of a document, without any markup, for the places where
+//! html can't be shown like previews or descriptions
+
+use std::fmt::{self, Write};
+use std::mem;
+
+use super::ast::{Alignment, Block, Document, Inline, TableCell};
+use super::compiler::{render_to_fmt_with_renderer, HtmlOptions};
+use super::highlight::Highlighters;
+use super::render::{RenderContext, Renderer};
+
+/// Options of the plain text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextOptions {
+    /// The most chars of a line, the paragraphs are wrapped to it. `None`
+    /// keeps each paragraph in a single line
+    pub width: Option<usize>,
+
+    /// If the code blocks are kept, as they are, or left out
+    pub code_blocks: bool,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions { width: Some(80), code_blocks: true }
+    }
+}
+
+/// The renderer of the plain text. The blocks are separated by blank lines,
+/// the headings of level 1 and 2 are underlined with `=` and `-` and the
+/// deeper ones start with their `###`, like in markdown, the lists
+/// have `- ` or their number and only the text of the links and the alt of
+/// the images are kept
+#[derive(Debug, Clone, Default)]
+pub struct TextRenderer {
+    options: TextOptions,
+
+    /// The text of the inlines of the block being rendered
+    text: String,
+
+    lines: Lines,
+}
+
+impl TextRenderer {
+    pub fn new(options: TextOptions) -> Self {
+        TextRenderer { options, ..TextRenderer::default() }
+    }
+
+    /// The text of the `inlines` as a single string, the hard breaks are
+    /// kept as line breaks
+    fn inline_text(&mut self, ctx: &mut RenderContext, inlines: &[Inline])
+        -> Result<String, fmt::Error> {
+        self.text.clear();
+        self.inlines(ctx, inlines)?;
+        Ok(mem::take(&mut self.text))
+    }
+}
+
+/// The layout of the lines of the blocks in a text, shared with the
+/// terminal renderer
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Lines {
+    /// The start of the lines, the indentation of the lists and the quotes
+    indent: String,
+
+    /// The bullets of the list items not written yet, with where they go in
+    /// the indent, they are written in the first line of the items. A list
+    /// can be the first block of an item, so there can be more than one
+    bullets: Vec<(usize, String)>,
+
+    /// If there is a block before in the same list item or quote, so the
+    /// next one goes after a blank line
+    after_block: bool,
+}
+
+impl Lines {
+    /// Writes a line with the indent, or with the bullets if it's the first
+    /// line of list items. The `line` is kept as it is, like the spaces at
+    /// the end of the code, only the indent of an empty one is trimmed
+    pub(crate) fn write_line(&mut self, ctx: &mut RenderContext, line: &str) -> fmt::Result {
+        let mut indent = self.indent.clone();
+        // From the last one, the bullets before it don't move it
+        for (at, bullet) in self.bullets.drain(..).rev() {
+            indent.replace_range(at..at + bullet.chars().count(), &bullet);
+        }
+        match line {
+            "" => writeln!(ctx, "{}", indent.trim_end()),
+            line => writeln!(ctx, "{}{}", indent, line),
+        }
+    }
+
+    /// Leaves a blank line if there is a block before
+    pub(crate) fn separate(&mut self, ctx: &mut RenderContext) -> fmt::Result {
+        if mem::replace(&mut self.after_block, true) {
+            ctx.write_char('\n')?;
+        }
+        Ok(())
+    }
+
+    /// The `width` left after the indent
+    pub(crate) fn width(&self, width: Option<usize>) -> Option<usize> {
+        width.map(|width| width.saturating_sub(self.indent.chars().count()).max(1))
+    }
+
+    /// Starts the blocks of a container, like a quote, with their lines
+    /// after the `prefix`. It gives the indent to restore with `end`
+    pub(crate) fn start(&mut self, prefix: &str) -> usize {
+        let indent = self.indent.len();
+        self.indent.push_str(prefix);
+        self.after_block = false;
+        indent
+    }
+
+    /// Starts a list item, its first line has the `bullet` and the rest are
+    /// indented as much as it
+    pub(crate) fn start_item(&mut self, bullet: String) -> usize {
+        let indent = self.start(&" ".repeat(bullet.chars().count()));
+        self.bullets.push((indent, bullet));
+        indent
+    }
+
+    /// Ends the blocks started with `start` or `start_item`, an item with
+    /// nothing written still has its bullet
+    pub(crate) fn end(&mut self, ctx: &mut RenderContext, indent: usize) -> fmt::Result {
+        if self.bullets.last().is_some_and(|(at, _)| *at >= indent) {
+            self.write_line(ctx, "")?;
+        }
+        self.indent.truncate(indent);
+        self.after_block = true;
+        Ok(())
+    }
+
+    /// Forgets the blocks before, the next one doesn't have a blank line
+    pub(crate) fn join(&mut self) {
+        self.after_block = false;
+    }
+}
+
+/// Splits the `text` in lines of at most `width` chars, a word longer than
+/// that is alone in its line
+fn wrap(text: &str, width: Option<usize>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut len = 0;
+        for word in paragraph.split_whitespace() {
+            let word_len = word.chars().count();
+            if len > 0 && width.is_some_and(|width| len + 1 + word_len > width) {
+                lines.push(mem::take(&mut line));
+                len = 0;
+            }
+            if len > 0 {
+                line.push(' ');
+                len += 1;
+            }
+            line.push_str(word);
+            len += word_len;
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+impl Renderer for TextRenderer {
+    fn document(&mut self, ctx: &mut RenderContext, blocks: &[Block]) -> fmt::Result {
+        self.lines.join();
+        blocks.iter().try_for_each(|block| self.block(ctx, block))
+    }
+
+    fn heading(&mut self, ctx: &mut RenderContext, level: usize, content: &[Inline],
+        _: Option<&str>) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let text = self.inline_text(ctx, content)?;
+        let text = wrap(&text, None).join(" ");
+        match level {
+            1 | 2 => {
+                self.lines.write_line(ctx, &text)?;
+                let underline = if level == 1 { "=" } else { "-" };
+                self.lines.write_line(ctx, &underline.repeat(text.chars().count()))
+            }
+            _ => self.lines.write_line(ctx, &format!("{} {}", "#".repeat(level), text)),
+        }
+    }
+
+    fn paragraph(&mut self, ctx: &mut RenderContext, content: &[Inline]) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let text = self.inline_text(ctx, content)?;
+        let width = self.lines.width(self.options.width);
+        for line in wrap(&text, width) {
+            self.lines.write_line(ctx, &line)?;
+        }
+        Ok(())
+    }
+
+    fn code_block(&mut self, ctx: &mut RenderContext, _: Option<&str>, code: &str)
+        -> fmt::Result {
+        if !self.options.code_blocks {
+            return Ok(());
+        }
+        self.lines.separate(ctx)?;
+        code.lines().try_for_each(|line| self.lines.write_line(ctx, line))
+    }
+
+    fn list(&mut self, ctx: &mut RenderContext, start: Option<usize>, tight: bool,
+        items: &[Vec<Block>]) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 && !tight {
+                ctx.write_char('\n')?;
+            }
+            let indent = self.lines.start_item(match start {
+                Some(start) => format!("{}. ", start + i),
+                None => "- ".to_owned(),
+            });
+            self.list_item(ctx, item, tight)?;
+            self.lines.end(ctx, indent)?;
+        }
+        Ok(())
+    }
+
+    fn list_item(&mut self, ctx: &mut RenderContext, blocks: &[Block], tight: bool)
+        -> fmt::Result {
+        for block in blocks {
+            if tight {
+                self.lines.join();
+            }
+            self.block(ctx, block)?;
+        }
+        Ok(())
+    }
+
+    fn quote(&mut self, ctx: &mut RenderContext, blocks: &[Block]) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let indent = self.lines.start("  ");
+        for block in blocks {
+            self.block(ctx, block)?;
+        }
+        self.lines.end(ctx, indent)
+    }
+
+    /// The columns are padded to be aligned
+    fn table(&mut self, ctx: &mut RenderContext, alignments: &[Alignment], header: &[TableCell],
+        rows: &[Vec<TableCell>]) -> fmt::Result {
+        self.lines.separate(ctx)?;
+        let mut texts = Vec::new();
+        for row in std::iter::once(header).chain(rows.iter().map(Vec::as_slice)) {
+            let mut cells = Vec::new();
+            for cell in row {
+                cells.push(wrap(&self.inline_text(ctx, &cell.content)?, None).join(" "));
+            }
+            texts.push(cells);
+        }
+        let widths: Vec<usize> = (0..alignments.len())
+            .map(|i| texts.iter().filter_map(|row| row.get(i)).map(|cell| cell.chars().count())
+                .max().unwrap_or(0))
+            .collect();
+        for row in texts {
+            let mut line = String::new();
+            for ((cell, width), alignment) in row.iter().zip(&widths).zip(alignments) {
+                if !line.is_empty() {
+                    line.push_str("  ");
+                }
+                match alignment {
+                    Alignment::Right => line.push_str(&format!("{:>1$}", cell, width)),
+                    Alignment::Center => line.push_str(&format!("{:^1$}", cell, width)),
+                    _ => line.push_str(&format!("{:1$}", cell, width)),
+                }
+            }
+            // The last column isn't padded
+            self.lines.write_line(ctx, line.trim_end())?;
+        }
+        Ok(())
+    }
+
+    fn thematic_break(&mut self, _: &mut RenderContext) -> fmt::Result {
+        Ok(())
+    }
+
+    fn table_of_contents(&mut self, _: &mut RenderContext) -> fmt::Result {
+        Ok(())
+    }
+
+    fn text(&mut self, _: &mut RenderContext, text: &str) -> fmt::Result {
+        self.text.push_str(text);
+        Ok(())
+    }
+
+    fn code(&mut self, _: &mut RenderContext, code: &str) -> fmt::Result {
+        self.text.push_str(code);
+        Ok(())
+    }
+
+    fn emphasis(&mut self, ctx: &mut RenderContext, children: &[Inline]) -> fmt::Result {
+        self.inlines(ctx, children)
+    }
+
+    fn strong(&mut self, ctx: &mut RenderContext, children: &[Inline]) -> fmt::Result {
+        self.inlines(ctx, children)
+    }
+
+    fn link(&mut self, ctx: &mut RenderContext, children: &[Inline], _: &str, _: Option<&str>)
+        -> fmt::Result {
+        self.inlines(ctx, children)
+    }
+
+    fn image(&mut self, _: &mut RenderContext, alt: &str, _: &str, _: Option<&str>)
+        -> fmt::Result {
+        self.text.push_str(alt);
+        Ok(())
+    }
+
+    fn soft_break(&mut self, _: &mut RenderContext) -> fmt::Result {
+        self.text.push(' ');
+        Ok(())
+    }
+
+    fn hard_break(&mut self, _: &mut RenderContext) -> fmt::Result {
+        self.text.push('\n');
+        Ok(())
+    }
+}
+
+/// The plain text of the `document` with the `options`, see `TextRenderer`
+pub fn render_text(document: &Document, options: &TextOptions) -> String {
+    let mut text = String::new();
+    let mut renderer = TextRenderer::new(options.clone());
+    // Writing into a `String` can't fail
+    let _ = render_to_fmt_with_renderer(document, &mut text, &mut renderer,
+        &Highlighters::default(), &HtmlOptions::default());
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BlockKind, InlineKind};
+    use crate::parse_markdown;
+    use crate::span::Span;
+
+    fn text(source: &str, width: Option<usize>, code_blocks: bool) -> String {
+        render_text(&parse_markdown(source), &TextOptions { width, code_blocks })
+    }
+
+    #[test]
+    fn text_tests() {
+        assert_eq!(text("# Title\nSome *very* **long** text with a [link](/a \"t\") and `code`\n\
+            ## Sub\n### Deep\n![alt](a.png) b  \nc", Some(20), true),
+            "Title\n=====\n\nSome very long text\nwith a link and code\n\nSub\n---\n\n\
+            ### Deep\n\nalt b\nc\n");
+
+        // The lists keep their bullets and indent their blocks
+        assert_eq!(text("- a\n- b\n  - c\n\n3. d\n\n   e\n4. f", None, true),
+            "- a\n- b\n  - c\n\n3. d\n\n   e\n\n4. f\n");
+        assert_eq!(text("- one two three four\n-", Some(10), true),
+            "- one two\n  three\n  four\n-\n");
+        // A list first in an item has the bullets of both
+        assert_eq!(text("- - a\n\n1. - b\n   - c\n\n- -", None, true),
+            "- - a\n\n1. - b\n   - c\n\n- -\n");
+
+        // The code blocks are kept or left out
+        let source = "> a\n> ```\n> let a;\n> ```\n\n---\nb";
+        assert_eq!(text(source, None, true), "  a\n\n  let a;\n\nb\n");
+        assert_eq!(text(source, None, false), "  a\n\nb\n");
+
+        // The code lines are kept as they are, only the indent is trimmed
+        assert_eq!(text("- ```\n  a  \n\n   b\n  ```", None, true), "- a  \n\n   b\n");
+
+        assert_eq!(text("| a | bb |\n|---|:-:|\n| ccc | d |", None, true),
+            "a    bb\nccc  d\n");
+
+        // A row of the ast without all its cells, they are left out as in
+        // the html
+        let cell = |text: &str| TableCell {
+            content: vec![Inline::new(InlineKind::Text(text.to_owned().into()), Span::default())],
+            span: Span::default(),
+        };
+        let document = Document { blocks: vec![Block::new(BlockKind::Table {
+            alignments: vec![Alignment::None, Alignment::Right],
+            header: vec![cell("a"), cell("b")],
+            rows: vec![vec![cell("ccc")], vec![]],
+        }, Span::default())] };
+        assert_eq!(render_text(&document, &TextOptions::default()), "a    b\nccc\n\n");
+    }
+}
@@ -0,0 +1,174 @@
+//! The table of contents of a document, the outline of its headings with the
+//! ids they have in the html
+
+use std::fmt;
+
+use super::ast::{self, Block, BlockKind, Document};
+use super::html::{EscapeHtml, EscapeUrl};
+use super::slug::Slugger;
+use super::span::Span;
+
+/// A heading of the outline of a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TocEntry {
+    pub level: usize,
+
+    /// The text of the heading without any format
+    pub text: String,
+
+    /// The id of the heading in the html, its explicit `{#id}` or the slug of
+    /// its text
+    pub slug: String,
+
+    /// The span of the whole heading
+    pub span: Span,
+}
+
+/// The levels of the headings that are in a table of contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TocOptions {
+    pub min_level: usize,
+    pub max_level: usize,
+}
+
+impl Default for TocOptions {
+    fn default() -> Self {
+        TocOptions { min_level: 1, max_level: 6 }
+    }
+}
+
+impl Document<'_> {
+    /// The headings of the document in order, also the ones inside the lists
+    /// and the quotes
+    pub fn outline(&self) -> Vec<TocEntry> {
+        outline(&self.blocks)
+    }
+}
+
+/// Calls `f` with every heading of the `blocks` in the order of the source
+fn for_each_heading<'a>(blocks: &'a [Block], f: &mut impl FnMut(&'a Block)) {
+    for block in blocks {
+        match &block.kind {
+            BlockKind::Heading { .. } => f(block),
+            BlockKind::List { items, .. } => {
+                items.iter().for_each(|item| for_each_heading(item, f));
+            }
+            BlockKind::Quote(blocks) => for_each_heading(blocks, f),
+            _ => {}
+        }
+    }
+}
+
+/// If there is a table of contents marker in the `blocks`, also inside the
+/// lists and the quotes
+pub(crate) fn has_toc(blocks: &[Block]) -> bool {
+    blocks.iter().any(|block| match &block.kind {
+        BlockKind::TableOfContents => true,
+        BlockKind::List { items, .. } => items.iter().any(|item| has_toc(item)),
+        BlockKind::Quote(blocks) => has_toc(blocks),
+        _ => false,
+    })
+}
+
+/// The outline of the headings in the `blocks`. The explicit ids are taken
+/// before any slug, so the slugs never repeat them even if they come first
+pub(crate) fn outline(blocks: &[Block]) -> Vec<TocEntry> {
+    let mut slugger = Slugger::new();
+    for_each_heading(blocks, &mut |block| {
+        if let BlockKind::Heading { id: Some(id), .. } = &block.kind {
+            slugger.add(id);
+        }
+    });
+
+    let mut entries = Vec::new();
+    for_each_heading(blocks, &mut |block| {
+        if let BlockKind::Heading { level, content, id } = &block.kind {
+            let text = ast::text(content);
+            let slug = match id {
+                Some(id) => id.to_string(),
+                None => slugger.slug(&text),
+            };
+            entries.push(TocEntry { level: *level, text, slug, span: block.span });
+        }
+    });
+    entries
+}
+
+/// Writes the nested `<ol>` of the `entries`, the ones after an entry with a
+/// greater level are its children
+fn toc_list(out: &mut dyn fmt::Write, entries: &[&TocEntry]) -> fmt::Result {
+    out.write_str("<ol>\n")?;
+    let mut i = 0;
+    while let Some(entry) = entries.get(i) {
+        let children = entries[i + 1..].iter().take_while(|e| e.level > entry.level).count();
+        write!(out, "<li><a href=\"#{}\">{}</a>", EscapeUrl(&entry.slug),
+            EscapeHtml(&entry.text))?;
+        if children > 0 {
+            out.write_char('\n')?;
+            toc_list(out, &entries[i + 1..i + 1 + children])?;
+        }
+        out.write_str("</li>\n")?;
+        i += 1 + children;
+    }
+    out.write_str("</ol>\n")
+}
+
+/// Writes the `entries` of an outline, with the levels of the `options`, as
+/// a `<nav>` with the nested `<ol>` of the headings linking to them
+pub(crate) fn write_toc(out: &mut dyn fmt::Write, entries: &[TocEntry], options: &TocOptions)
+    -> fmt::Result {
+    let entries: Vec<&TocEntry> = entries.iter()
+        .filter(|e| (options.min_level..=options.max_level).contains(&e.level))
+        .collect();
+    out.write_str("<nav class=\"toc\">\n")?;
+    if !entries.is_empty() {
+        toc_list(out, &entries)?;
+    }
+    out.write_str("</nav>")
+}
+
+/// Renders the `entries` of an outline as html, see `write_toc`
+pub fn render_toc(entries: &[TocEntry], options: &TocOptions) -> String {
+    let mut html = String::new();
+    // Writing into a `String` can't fail
+    let _ = write_toc(&mut html, entries, options);
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_markdown;
+    use crate::span::Position;
+
+    #[test]
+    fn toc_tests() {
+        let document = parse_markdown("# A & B\n## Intro\n> ## Intro\n#### Deep {#intro}\n\nC\n=");
+        let outline = document.outline();
+        let slugs: Vec<(usize, &str, &str)> = outline.iter()
+            .map(|e| (e.level, e.text.as_str(), e.slug.as_str()))
+            .collect();
+        assert_eq!(slugs, vec![
+            (1, "A & B", "a--b"),
+            (2, "Intro", "intro-1"),
+            (2, "Intro", "intro-2"),
+            (4, "Deep", "intro"),
+            (1, "C", "c"),
+        ]);
+        assert_eq!(outline[4].span.start, Position { offset: 48, line: 5, column: 0 });
+
+        // The skipped levels are nested once
+        assert_eq!(render_toc(&outline, &TocOptions::default()), "<nav class=\"toc\">\n<ol>\n\
+            <li><a href=\"#a--b\">A &amp; B</a>\n<ol>\n\
+            <li><a href=\"#intro-1\">Intro</a></li>\n\
+            <li><a href=\"#intro-2\">Intro</a>\n<ol>\n<li><a href=\"#intro\">Deep</a></li>\n</ol>\n\
+            </li>\n</ol>\n</li>\n\
+            <li><a href=\"#c\">C</a></li>\n</ol>\n</nav>");
+
+        let options = TocOptions { min_level: 2, max_level: 3 };
+        assert_eq!(render_toc(&outline, &options), "<nav class=\"toc\">\n<ol>\n\
+            <li><a href=\"#intro-1\">Intro</a></li>\n\
+            <li><a href=\"#intro-2\">Intro</a></li>\n</ol>\n</nav>");
+        assert_eq!(render_toc(&[], &options), "<nav class=\"toc\">\n</nav>");
+    }
+}
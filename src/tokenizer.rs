@@ -1,15 +1,21 @@
-use std::iter::{Peekable, DoubleEndedIterator};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::iter::Peekable;
 use std::str::Chars;
 
+use super::errors::ParserError;
+use super::span::{Position, Span};
+
 /// Representation of a Markdown Token, the ::Code one can be the more tricky
-/// Because to appear must have 1 or 3 ::ReversedQuote s preceding and 
-/// at the end, its not an ::String because it can contain reserved tokens 
-/// inside
+/// Because to appear must have the same number of ::ReversedQuote s preceding
+/// and at the end (unless it's an unclosed fence that lasts until the end),
+/// its not an ::String because it can contain reserved tokens inside
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Token {
+pub enum Token<'a> {
     /// #: for headers
     Pad, 
-    String(String),
+    /// The text, borrowed from the source unless it has escapes
+    String(Cow<'a, str>),
     /// \n
     NewLine, 
     /// `: for code snippets
@@ -18,180 +24,541 @@ pub enum Token {
     LowBar,   
     /// *: for bold
     Asterisk, 
+    /// [: for links
+    OpenBracket,
+    /// ]: for links
+    CloseBracket,
+    /// !: only when followed by `[`, for images
+    Bang,
+    /// The spaces at the start of a line or after a list marker, with the
+    /// tabs expanded to multiples of 4 columns
+    Indent(usize),
+    /// -, + or * followed by an space at the start of a line: for lists
+    Bullet(char),
+    /// 1. or 1) followed by an space at the start of a line: for ordered lists
+    ListNumber(usize, char),
+    /// >: at the start of a line, for block quotes
+    BlockQuote,
+    /// |: for the cells of the tables
+    Pipe,
     /// ^ Read enum descr.
-    Code(String), 
+    Code(Cow<'a, str>), 
+}
+
+impl<'a> Token<'a> {
+    /// The text from the source that gave the token
+    pub fn literal(&self) -> Cow<'_, str> {
+        Cow::Borrowed(match self {
+            Token::Pad => "#",
+            Token::String(s) | Token::Code(s) => &**s,
+            Token::NewLine => "\n",
+            Token::ReversedQuote => "`",
+            Token::LowBar => "_",
+            Token::Asterisk => "*",
+            Token::OpenBracket => "[",
+            Token::CloseBracket => "]",
+            Token::Bang => "!",
+            Token::BlockQuote => ">",
+            Token::Pipe => "|",
+            Token::Indent(n) => return Cow::Owned(" ".repeat(*n)),
+            Token::Bullet(ch) => return Cow::Owned(ch.to_string()),
+            Token::ListNumber(n, ch) => return Cow::Owned(format!("{}{}", n, ch)),
+        })
+    }
+
+    /// The same token but owning its text, so it can outlive the source
+    pub fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::String(s) => Token::String(Cow::Owned(s.into_owned())),
+            Token::Code(s) => Token::Code(Cow::Owned(s.into_owned())),
+            Token::Pad => Token::Pad,
+            Token::NewLine => Token::NewLine,
+            Token::ReversedQuote => Token::ReversedQuote,
+            Token::LowBar => Token::LowBar,
+            Token::Asterisk => Token::Asterisk,
+            Token::OpenBracket => Token::OpenBracket,
+            Token::CloseBracket => Token::CloseBracket,
+            Token::Bang => Token::Bang,
+            Token::Indent(n) => Token::Indent(n),
+            Token::Bullet(ch) => Token::Bullet(ch),
+            Token::ListNumber(n, ch) => Token::ListNumber(n, ch),
+            Token::BlockQuote => Token::BlockQuote,
+            Token::Pipe => Token::Pipe,
+        }
+    }
 }
 
 /// The TokenizerIterator is an Iterator that given a source and some indle-status
-/// control variables will yield Tokens consuming the source
-struct TokenizerIterator<'a> {
+/// control variables will yield Tokens, with their span, consuming the source
+pub(crate) struct TokenizerIterator<'a> {
+    /// The whole source, the text of the tokens is borrowed from it
+    text: &'a str,
+
     /// Source Iterator that contains the raw text to tokenize
     source: Peekable<Chars<'a>>,
 
-    /// Control variables
-    /// TODO: Replace them with an enum for simpler and cleaner code
-    number_of_quotes: usize,
-    possible_code: bool,
-    done_code: bool,
+    /// Tokens already tokenized waiting to be yielded, a code snippet gives
+    /// all its tokens at once
+    pending: VecDeque<(Token<'a>, Span)>,
+
+    /// If the last yielded token was a `Token::NewLine` (or nothing was
+    /// yielded yet), a `Token::Indent` or a list marker, there the spaces are
+    /// indentation and the list markers can appear
+    line_start: bool,
+
+    /// If the current line has a `Token::BlockQuote`, the code fences there
+    /// have the `>` of the quote at the start of their lines
+    quoted: bool,
+
+    /// The byte offset, the line and the line-width of the next char
+    offset: usize,
+    line: usize,
+    width: usize,
+
+    /// The column of the next char, like the line-width but with the tabs
+    /// expanded
+    column: usize,
+
+    /// Found errors, the unsupported chars are kept as text
+    errors: Vec<ParserError>,
 }
 
 impl<'a> TokenizerIterator<'a> {
     /// Instantiates a new Tokenizer given the input `source`
-    fn new(source: &'a str) -> Self {
+    pub(crate) fn new(source: &'a str) -> Self {
         TokenizerIterator {
+            text: source,
             source: source.chars().peekable(),
+            pending: VecDeque::new(),
+
+            line_start: true,
+            quoted: false,
+
+            offset: 0,
+            line: 0,
+            width: 0,
+            column: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Consumes the next char of the source keeping track of the position,
+    /// the `\r\n` and `\r` line endings are given as `\n`
+    fn bump(&mut self) -> Option<char> {
+        let mut ch = self.source.next()?;
+        self.offset += ch.len_utf8();
+        if ch == '\r' {
+            if self.source.next_if_eq(&'\n').is_some() {
+                self.offset += 1;
+            }
+            ch = '\n';
+        }
+        if ch == '\n' {
+            self.line += 1;
+            self.width = 0;
+            self.column = 0;
+        } else {
+            self.width += 1;
+            self.column += if ch == '\t' { 4 - self.column % 4 } else { 1 };
+        }
+        Some(ch)
+    }
+
+    /// The position of the next char
+    fn here(&self) -> Position {
+        Position { offset: self.offset, line: self.line, column: self.width }
+    }
+
+    /// Parses the indentation after its first space or tab, the `column` is
+    /// the one where it started
+    fn parse_indent(&mut self, column: usize) -> Token<'a> {
+        while let Some(' ') | Some('\t') = self.source.peek() {
+            self.bump();
+        }
+        Token::Indent(self.column - column)
+    }
+
+    /// If the current char is followed by an space, a tab or the end of the
+    /// line, what a list marker needs
+    fn marker_end_ahead(&self) -> bool {
+        matches!(self.source.clone().next(), None | Some(' ') | Some('\t') | Some('\n') | Some('\r'))
+    }
+
+    /// If the current `#` is followed by at most 5 more and then by an space,
+    /// a tab or the end of the line, what the `#`s of a header need
+    fn header_ahead(&self) -> bool {
+        let mut ahead = self.source.clone();
+        let mut pads = 1;
+        while ahead.next_if_eq(&'#').is_some() {
+            pads += 1;
+        }
+        pads <= 6 && matches!(ahead.next(), None | Some(' ') | Some('\t') | Some('\n') | Some('\r'))
+    }
+
+    /// Parses the `1.` or `1)` of an ordered list after its first digit, if it
+    /// is not a list marker nothing is consumed
+    fn parse_list_number(&mut self, first_digit: char) -> Option<Token<'a>> {
+        let mut ahead = self.source.clone();
+        let mut number = first_digit.to_string();
+        while let Some(ch) = ahead.next_if(char::is_ascii_digit) {
+            number.push(ch);
+        }
+        let delimiter = ahead.next_if(|ch| *ch == '.' || *ch == ')')?;
+        if number.len() > 9
+            || !matches!(ahead.peek(), None | Some(' ') | Some('\t') | Some('\n') | Some('\r')) {
+            return None;
+        }
 
-            number_of_quotes: 0,
-            possible_code: false,
-            done_code: false,
+        for _ in 0..number.len() {
+            self.bump();
         }
+        Some(Token::ListNumber(number.parse().ok()?, delimiter))
     }
 
     /// Internal method that parses from the source a String, that its no more
-    /// than the common text presented in markdown
-    fn parse_string(&mut self, first_ch: char) -> String {
-        let mut string = String::new();
-        string.push(first_ch);
+    /// than the common text presented in markdown, every char that has no
+    /// meaning in markdown is accepted. It starts at the byte `start` after
+    /// its first char, that is `replaced` if it isn't the one of the source,
+    /// and it's only copied when a char has to be replaced
+    fn parse_string(&mut self, start: usize, replaced: Option<char>) -> Cow<'a, str> {
+        let mut string = replaced.map(String::from);
         while let Some(&ch) = self.source.peek() {
-            match ch {
-                'a'..='z' | 'A'..='Z' | '0'..='9' | '\'' | ' '
-                | '"' | '.' | '?' | '¿' | ',' | ':' | '(' | ')' => {
-                    string.push(ch);
-                    self.source.next();
+            let replaced = match ch {
+                '\n' | '\r' | '#' | '_' | '`' | '*' | '[' | ']' | '|' => break,
+                '!' if self.image_ahead() => break,
+                '\\' => {
+                    let text = &self.text[start..self.offset];
+                    self.bump();
+                    self.parse_escape().map(|ch| (text, ch))
+                }
+                '\0' => {
+                    let text = &self.text[start..self.offset];
+                    let at = self.here();
+                    self.bump();
+                    self.errors.push(
+                        ParserError::UnexpectedChar(Span::new(at, self.here()), ch)
+                    );
+                    Some((text, char::REPLACEMENT_CHARACTER))
                 }
-                '\n' | '#' | '_' | '`' | '*' => break,
-                _ => panic!("Unexpected char while parsing string: {}", ch),
+                _ => {
+                    self.bump();
+                    None
+                }
+            };
+            match (&mut string, replaced) {
+                (Some(string), Some((_, ch))) => string.push(ch),
+                (Some(string), None) => string.push(ch),
+                (None, Some((text, ch))) => string = Some(format!("{}{}", text, ch)),
+                (None, None) => {}
             }
         }
-        string
+        match string {
+            Some(string) => Cow::Owned(string),
+            None => Cow::Borrowed(&self.text[start..self.offset]),
+        }
     }
 
-    /// Internal method that parses from the source code, its less restrictive
-    /// than `self.parse_string` because code snippets can contain 
-    /// reserved tokens
-    fn parse_code(&mut self, first_ch: char) -> String {
-        let mut string = String::new();
-        string.push(first_ch);
-        while let Some(&ch) = self.source.peek() {
+    /// Parses the char after a `\\`, an ASCII punctuation char is escaped so
+    /// it has no meaning in markdown, otherwise the `\\` is just text and
+    /// there is no escaped char
+    fn parse_escape(&mut self) -> Option<char> {
+        match self.source.peek() {
+            Some(&ch) if ch.is_ascii_punctuation() => {
+                self.bump();
+                Some(ch)
+            }
+            _ => None,
+        }
+    }
+
+    /// If the next chars are `![`, the start of an image
+    fn image_ahead(&self) -> bool {
+        let mut ahead = self.source.clone();
+        ahead.next() == Some('!') && ahead.next() == Some('[')
+    }
+
+    /// Looks ahead for the `quotes` ReversedQuotes that close the code snippet
+    /// that starts at the current char, returning the number of chars of the
+    /// code. A snippet inside a line can't contain an empty line, and a
+    /// `fence` is only closed by at least `quotes` quotes at the start of a
+    /// line, after its spaces or tabs and the `>`s if it's `quoted`
+    fn find_code_end(&self, quotes: usize, fence: bool) -> Option<usize> {
+        let mut ahead = self.source.clone();
+        let mut len = 0;
+        // The length of the code before the current line
+        let mut line_len = 0;
+        let mut line_start = false;
+        let mut blank_line = false;
+        while let Some(mut ch) = ahead.next() {
+            // The `\r\n` line endings are a single char as in `bump`
+            if ch == '\r' {
+                ahead.next_if_eq(&'\n');
+                ch = '\n';
+            }
             match ch {
-                '`' => break,
-                // Allow every kind of char to be put into the code snippet
+                '`' => {
+                    let mut run = 1;
+                    while ahead.next_if_eq(&'`').is_some() {
+                        run += 1;
+                    }
+                    if fence && line_start && run >= quotes {
+                        return Some(line_len);
+                    } else if !fence && run == quotes {
+                        return Some(len);
+                    }
+                    len += run;
+                    line_start = false;
+                    blank_line = false;
+                }
+                '\n' => {
+                    if !fence && blank_line {
+                        return None;
+                    }
+                    len += 1;
+                    line_len = len;
+                    line_start = true;
+                    blank_line = true;
+                }
+                ' ' | '\t' => len += 1,
+                '>' if self.quoted && line_start => len += 1,
                 _ => {
-                    string.push(ch);
-                    self.source.next();   
+                    len += 1;
+                    line_start = false;
+                    blank_line = false;
                 }
             }
         }
-        string
+        None
+    }
+
+    /// Adds to the pending tokens the `quotes` ReversedQuotes from `start`
+    fn push_quotes(&mut self, start: Position, quotes: usize) {
+        for i in 0..quotes {
+            let span = Span::new(start.forward(i), start.forward(i + 1));
+            self.pending.push_back((Token::ReversedQuote, span));
+        }
+    }
+
+    /// The errors found while tokenizing, once all the source is tokenized
+    pub(crate) fn into_errors(self) -> Vec<ParserError> {
+        self.errors
+    }
+
+    /// Internal method that parses a code snippet after its first `quotes`
+    /// ReversedQuotes, that start at `start`. The code snippets can contain
+    /// reserved tokens and if it can't be closed the quotes are just text
+    fn parse_code(&mut self, start: Position, quotes: usize) -> (Token<'a>, Span) {
+        // A fence starts a line and, if not closed, lasts until the end
+        let fence = quotes >= 3 && self.line_start;
+        let len = match self.find_code_end(quotes, fence) {
+            Some(len) => Some(len),
+            None if fence => None,
+            None => {
+                let span = Span::new(start, self.here());
+                return (Token::String(Cow::Borrowed(&self.text[span.range()])), span);
+            }
+        };
+
+        self.push_quotes(start, quotes);
+        let code_start = self.here();
+        match len {
+            Some(len) => for _ in 0..len {
+                self.bump();
+            },
+            None => while self.bump().is_some() {},
+        }
+        // Only the `\r` line endings have to be replaced
+        let code = &self.text[code_start.offset..self.offset];
+        let code = if code.contains('\r') {
+            Cow::Owned(code.replace("\r\n", "\n").replace('\r', "\n"))
+        } else {
+            Cow::Borrowed(code)
+        };
+        self.pending.push_back((Token::Code(code), Span::new(code_start, self.here())));
+        if len.is_some() {
+            // The closing fence can be indented, quoted, and longer than the
+            // opening, the extra quotes are part of the last one
+            while matches!(self.source.peek(), Some(' ') | Some('\t'))
+                || (fence && self.quoted && self.source.peek() == Some(&'>')) {
+                self.bump();
+            }
+            let closing = self.here();
+            for _ in 0..quotes {
+                self.bump();
+            }
+            while fence && self.source.peek() == Some(&'`') {
+                self.bump();
+            }
+            self.push_quotes(closing, quotes);
+            let end = self.here();
+            if let Some((_, span)) = self.pending.back_mut() {
+                span.end = end;
+            }
+        }
+        self.pending.pop_front().unwrap()
     }
 }
 
 /// Iterator imlementation for the token, the main usage that the struct'll have
 impl<'a> Iterator for TokenizerIterator<'a> {
-    type Item = Token;
+    type Item = (Token<'a>, Span);
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some(ch) = self.source.next() {
-            return Some(match ch {
-                'a'..='z' | 'A'..='Z' | '0'..='9' 
-                | '"' | '.' | '?' | '¿' | ',' | ':' | '(' | ')' => {
-                    if self.possible_code {
-                        let code_string = self.parse_code(ch);
-                        self.done_code = true;
-                        Token::Code(code_string)
-                    } else {
-                        let string = self.parse_string(ch);
-                        Token::String(string)
-                    }
+        if let Some(token) = self.pending.pop_front() {
+            return Some(token);
+        }
+        let start = self.here();
+        let column = self.column;
+        let ch = self.bump()?;
+        let token = match ch {
+            '`'  => {
+                let mut quotes = 1;
+                while self.source.peek() == Some(&'`') {
+                    self.bump();
+                    quotes += 1;
                 }
-                ' '  => {
-                    // The code snippets can start with an space but md not
-                    if self.possible_code {
-                        let code_string = self.parse_code(ch);
-                        self.done_code = true;
-                        Token::Code(code_string)
-                    } else {
-                        continue;
-                    }
-                },
-                '\n' => Token::NewLine,
-                '#'  => Token::Pad,
-                '_'  => Token::LowBar,
-                '`'  => {
-                    // A little tricky logic to allow single ReversedQuote and
-                    // triple ReversedQuote code snippets
-                    self.number_of_quotes += 1;
-                    if self.number_of_quotes == 1 
-                        || self.number_of_quotes == 3 {
-                        self.possible_code = true;
-                    } else {
-                        self.possible_code = false;
-                    }
-                    if self.done_code && (self.number_of_quotes == 6 
-                        || self.number_of_quotes == 2) {
-                        self.number_of_quotes = 0;
-                        self.done_code = false;
-                        self.possible_code = false;
-                    }
-                    Token::ReversedQuote
-
-                },
-                '*'  => Token::Asterisk,
-                _    => panic!("Unexpected char: {}", ch),
-            });
+                let (token, span) = self.parse_code(start, quotes);
+                self.line_start = false;
+                return Some((token, span));
+            },
+            '!' if self.source.peek() == Some(&'[') => Token::Bang,
+            ' ' | '\t' if self.line_start => self.parse_indent(column),
+            '-' | '+' | '*' if self.line_start && self.marker_end_ahead() => {
+                Token::Bullet(ch)
+            }
+            '>' if self.line_start => Token::BlockQuote,
+            // A `#hashtag` or more than 6 `#`s are not a header
+            '#' if self.line_start && !self.header_ahead() => {
+                while self.source.peek() == Some(&'#') {
+                    self.bump();
+                }
+                Token::String(Cow::Borrowed(&self.text[start.offset..self.offset]))
+            }
+            '0'..='9' if self.line_start => match self.parse_list_number(ch) {
+                Some(token) => token,
+                None => Token::String(self.parse_string(start.offset, None)),
+            },
+            '\n' => Token::NewLine,
+            '#'  => Token::Pad,
+            '_'  => Token::LowBar,
+            '*'  => Token::Asterisk,
+            '['  => Token::OpenBracket,
+            ']'  => Token::CloseBracket,
+            '|'  => Token::Pipe,
+            '\\' => {
+                let escaped = self.parse_escape();
+                Token::String(self.parse_string(start.offset, escaped))
+            }
+            '\0' => {
+                self.errors.push(
+                    ParserError::UnexpectedChar(Span::new(start, self.here()), ch)
+                );
+                let ch = char::REPLACEMENT_CHARACTER;
+                Token::String(self.parse_string(start.offset, Some(ch)))
+            }
+            _    => Token::String(self.parse_string(start.offset, None)),
+        };
+        self.line_start = matches!(token, Token::NewLine | Token::Indent(_)
+            | Token::Bullet(_) | Token::ListNumber(..) | Token::BlockQuote);
+        match token {
+            Token::NewLine => self.quoted = false,
+            Token::BlockQuote => self.quoted = true,
+            _ => {}
         }
-        None
+        Some((token, Span::new(start, self.here())))
     }
 }
 
 /// Abstration of the TokenizerIterator to allow a Double ended Itearator, as 
 /// result of this especific implementation its not only a DEIterator, its also
 /// a circular iterator, once you get to the end after a `None` the iterator 
-/// starts from the begining
+/// starts from the begining. It keeps all the tokens, the parser takes them
+/// from a TokenizerIterator instead while it parses, so only the tests use it
+#[cfg(test)]
 #[derive(Debug)]
-pub struct Tokenizer {
+pub struct Tokenizer<'a> {
+    /// The source of the tokens, their text is borrowed from it
+    source: &'a str,
+
     /// The tokens already calculated
-    tokens: Vec<Token>,
+    tokens: Vec<Token<'a>>,
+
+    /// The span of each token in the source
+    spans: Vec<Span>,
 
     /// The current token that its an optional tuple of the token and the 
     /// corresponding index
-    curr_token: Option<(usize, Token)>,
+    curr_token: Option<(usize, Token<'a>)>,
+
+    /// The errors found while tokenizing
+    errors: Vec<ParserError>,
 }
 
-impl Tokenizer {
+#[cfg(test)]
+impl<'a> Tokenizer<'a> {
     /// Instantiates a new `Tokenizer`, in the process pre tokenizes all the 
     /// source, so this instantiation can be slow
-    pub fn new(source: &str) -> Self {
-        let mut tok_iter = TokenizerIterator::new(source);
-        let mut tokens = vec![];
-
+    pub fn new(source: &'a str) -> Self {
         // Collect the tokens
-        while let Some(t) = tok_iter.next() {
-            tokens.push(t);
-        }
+        let mut tok_iter = TokenizerIterator::new(source);
+        let (tokens, spans) = tok_iter.by_ref().unzip();
 
         Tokenizer {
+            source,
             tokens,
+            spans,
             curr_token: None,
+            errors: tok_iter.errors,
         }
     }
 
     /// Replaces the tokenizer state with a new source recalculating all the 
     /// tokens, as the instantiation this can be slow
-    pub fn reset(&mut self, source: &str) {
+    pub fn reset(&mut self, source: &'a str) {
         let mut tok_iter = TokenizerIterator::new(source);
+        self.source = source;
         self.tokens.clear();
+        self.spans.clear();
 
         // Collect the tokens
-        while let Some(t) = tok_iter.next() {
-            self.tokens.push(t);
+        for (token, span) in tok_iter.by_ref() {
+            self.tokens.push(token);
+            self.spans.push(span);
         }
         self.curr_token = None;
+        self.errors = tok_iter.errors;
+    }
+
+    /// The source that was tokenized
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// The tokens already calculated
+    pub fn tokens(&self) -> &[Token<'a>] {
+        &self.tokens
+    }
+
+    /// The span in the source of each of the `tokens`
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// The errors found while tokenizing, the text with them is still
+    /// tokenized
+    pub fn errors(&self) -> &[ParserError] {
+        &self.errors
+    }
+
+    /// Takes the tokens, their spans and the errors without copying them
+    pub fn into_parts(self) -> (Vec<Token<'a>>, Vec<Span>, Vec<ParserError>) {
+        (self.tokens, self.spans, self.errors)
     }
 }
 
-impl Iterator for Tokenizer {
-    type Item = Token;
+#[cfg(test)]
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
         // If the idx == last one it returns None, if it's None it returns 
@@ -216,7 +583,8 @@ impl Iterator for Tokenizer {
     }
 }
 
-impl DoubleEndedIterator for Tokenizer {
+#[cfg(test)]
+impl DoubleEndedIterator for Tokenizer<'_> {
     fn next_back(&mut self) -> Option<Self::Item> {
         // If the idx == 0 it returns None, if it's None it returns the last
         // element, otherwise just `next_back`. As result its a circular iterator
@@ -253,14 +621,14 @@ mod tests {
         let mut tokenizer = Tokenizer::new(simple_text);
 
         let mut tokens = vec![];
-        while let Some(t) = tokenizer.next() {
+        for t in tokenizer.by_ref() {
             tokens.push(t);
         }
         assert_eq!(tokens, 
             vec![
-                Token::Pad, Token::String("This is a big header".to_owned()), Token::NewLine, 
-                Token::String("This is normal code".to_owned()), Token::NewLine,
-                Token::ReversedQuote, Token::Code("This is a code snippet".to_owned()), Token::ReversedQuote
+                Token::Pad, Token::String(" This is a big header".into()), Token::NewLine, 
+                Token::String("This is normal code".into()), Token::NewLine,
+                Token::ReversedQuote, Token::Code("This is a code snippet".into()), Token::ReversedQuote
             ]
         );
 
@@ -271,14 +639,14 @@ fn main() {\n\tlet a = vec![];\n\treturn a;\n}
 ```";
         tokenizer.reset(code_text);
         let mut tokens = vec![];
-        while let Some(t) = tokenizer.next() {
+        for t in tokenizer.by_ref() {
             tokens.push(t);
         }
         assert_eq!(tokens, 
             vec![
-                Token::NewLine, Token::Pad, Token::String("Hello World".to_owned()),
+                Token::NewLine, Token::Pad, Token::String(" Hello World".into()),
                 Token::ReversedQuote, Token::ReversedQuote, Token::ReversedQuote,
-                Token::Code("rust\nfn main() {\n\tlet a = vec![];\n\treturn a;\n}\n".to_owned()),
+                Token::Code("rust\nfn main() {\n\tlet a = vec![];\n\treturn a;\n}\n".into()),
                 Token::ReversedQuote, Token::ReversedQuote, Token::ReversedQuote,
             ]
         );
@@ -286,7 +654,7 @@ fn main() {\n\tlet a = vec![];\n\treturn a;\n}
 
     #[test]
     fn double_ended_tokenizer_iterator_test() {
-        let mut tokenizer = Tokenizer::new("#***");
+        let mut tokenizer = Tokenizer::new("#\n**");
         tokenizer.next();
         tokenizer.next();
         tokenizer.next();
@@ -296,4 +664,166 @@ fn main() {\n\tlet a = vec![];\n\treturn a;\n}
         tokenizer.next_back();
         assert_eq!(Some(Token::Pad), tokenizer.next_back());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unclosed_code_tests() {
+        let tokens = |source| -> Vec<Token> { Tokenizer::new(source).tokens().to_vec() };
+
+        // Quotes without end are text
+        assert_eq!(tokens("it`s"), vec![
+            Token::String("it".into()),
+            Token::String("`".into()),
+            Token::String("s".into()),
+        ]);
+        assert_eq!(tokens("``a`b``"), vec![
+            Token::ReversedQuote, Token::ReversedQuote,
+            Token::Code("a`b".into()),
+            Token::ReversedQuote, Token::ReversedQuote,
+        ]);
+
+        // A fence without end lasts until the end
+        assert_eq!(tokens("```c\nint a;"), vec![
+            Token::ReversedQuote, Token::ReversedQuote, Token::ReversedQuote,
+            Token::Code("c\nint a;".into()),
+        ]);
+
+        // The closing fence can be indented with tabs
+        assert_eq!(tokens("```\n\tx\n \t```\ny"), vec![
+            Token::ReversedQuote, Token::ReversedQuote, Token::ReversedQuote,
+            Token::Code("\n\tx\n".into()),
+            Token::ReversedQuote, Token::ReversedQuote, Token::ReversedQuote,
+            Token::NewLine, Token::String("y".into()),
+        ]);
+
+        // The chars that can't be in the html are replaced
+        let tokenizer = Tokenizer::new("a\n\0b");
+        assert_eq!(tokenizer.tokens(), &[
+            Token::String("a".into()), Token::NewLine,
+            Token::String("\u{FFFD}b".into()),
+        ]);
+        let at = Position { offset: 2, line: 1, column: 0 };
+        assert_eq!(tokenizer.errors(),
+            &[ParserError::UnexpectedChar(Span::new(at, at.forward(1)), '\0')]);
+    }
+
+    #[test]
+    fn unicode_tests() {
+        let tokens = |source| -> Vec<Token> { Tokenizer::new(source).tokens().to_vec() };
+
+        // Multi-byte chars and grapheme clusters, combining accent, flag and
+        // emoji joined with ZWJ, are just text
+        for text in &["¡Hola! ¿Qué tal? Está mañana", "日本語のテキスト",
+            "cafe\u{301} 🇪🇸 👨\u{200d}👩\u{200d}👧", "1 - 2; 50% {x} <y> $ ^ \t"] {
+            let tokenizer = Tokenizer::new(text);
+            assert_eq!(tokenizer.tokens(), &[Token::String((*text).into())]);
+            assert!(tokenizer.errors().is_empty());
+        }
+
+        assert_eq!(tokens("día_*ñ*"), vec![
+            Token::String("día".into()), Token::LowBar,
+            Token::Asterisk, Token::String("ñ".into()), Token::Asterisk,
+        ]);
+
+        // The text is borrowed from the source unless it has escapes
+        let tokenizer = Tokenizer::new("día \\a `c`\n\\*b\r\n```\r\nd\r\n```");
+        let borrowed: Vec<bool> = tokenizer.tokens().iter()
+            .filter_map(|t| match t {
+                Token::String(s) | Token::Code(s) => Some(matches!(s, Cow::Borrowed(_))),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(borrowed, vec![true, true, false, false]);
+        assert_eq!(tokenizer.tokens()[5], Token::String("*b".into()));
+        assert_eq!(tokenizer.tokens()[10], Token::Code("\nd\n".into()));
+
+        // Escaped and line endings
+        assert_eq!(tokens("\\# \\*no\\* \\a\r\n\tb\rc"), vec![
+            Token::String("# *no* \\a".into()), Token::NewLine,
+            Token::Indent(4), Token::String("b".into()), Token::NewLine,
+            Token::String("c".into()),
+        ]);
+    }
+
+    #[test]
+    fn list_marker_tests() {
+        let tokens = |source| -> Vec<Token> { Tokenizer::new(source).tokens().to_vec() };
+
+        assert_eq!(tokens("- a\n  * b\n\t+\tc\n10) d"), vec![
+            Token::Bullet('-'), Token::Indent(1), Token::String("a".into()),
+            Token::NewLine,
+            Token::Indent(2), Token::Bullet('*'), Token::Indent(1),
+            Token::String("b".into()), Token::NewLine,
+            Token::Indent(4), Token::Bullet('+'), Token::Indent(3),
+            Token::String("c".into()), Token::NewLine,
+            Token::ListNumber(10, ')'), Token::Indent(1), Token::String("d".into()),
+        ]);
+
+        // Not at the start of a line or without space after
+        assert_eq!(tokens("a - b\n-1\n2.5 *c*"), vec![
+            Token::String("a - b".into()), Token::NewLine,
+            Token::String("-1".into()), Token::NewLine,
+            Token::String("2.5 ".into()), Token::Asterisk,
+            Token::String("c".into()), Token::Asterisk,
+        ]);
+    }
+
+    #[test]
+    fn span_tests() {
+        let spans = |source| -> Vec<(String, usize, usize)> {
+            let tokenizer = Tokenizer::new(source);
+            tokenizer.spans().iter()
+                .map(|span| (source[span.range()].to_owned(), span.start.line, span.start.column))
+                .collect()
+        };
+        let span = |text: &str, line, column| (text.to_owned(), line, column);
+
+        // The multi-byte chars, the tabs and the escapes take their bytes
+        assert_eq!(spans("# día\\_\r\n\t- *ñ*"), vec![
+            span("#", 0, 0), span(" día\\_", 0, 1), span("\r\n", 0, 7),
+            span("\t", 1, 0), span("-", 1, 1), span(" ", 1, 2), span("*", 1, 3),
+            span("ñ", 1, 4), span("*", 1, 5),
+        ]);
+
+        // Each quote of the code, the extra quotes of a fence are in the last
+        assert_eq!(spans("``a``\n```\nb\n````"), vec![
+            span("`", 0, 0), span("`", 0, 1), span("a", 0, 2), span("`", 0, 3),
+            span("`", 0, 4), span("\n", 0, 5),
+            span("`", 1, 0), span("`", 1, 1), span("`", 1, 2), span("\nb\n", 1, 3),
+            span("`", 3, 0), span("`", 3, 1), span("``", 3, 2),
+        ]);
+        assert_eq!(spans("a``b"), vec![span("a", 0, 0), span("``", 0, 1), span("b", 0, 3)]);
+    }
+
+    #[test]
+    fn block_quote_tests() {
+        let tokens = |source| -> Vec<Token> { Tokenizer::new(source).tokens().to_vec() };
+
+        assert_eq!(tokens("> a > b\n>> - c\n  >"), vec![
+            Token::BlockQuote, Token::Indent(1), Token::String("a > b".into()),
+            Token::NewLine,
+            Token::BlockQuote, Token::BlockQuote, Token::Indent(1), Token::Bullet('-'),
+            Token::Indent(1), Token::String("c".into()), Token::NewLine,
+            Token::Indent(2), Token::BlockQuote,
+        ]);
+
+        // The quoted fences are closed after the `>`
+        assert_eq!(tokens("> ```\n> a\n> ```\nb"), vec![
+            Token::BlockQuote, Token::Indent(1),
+            Token::ReversedQuote, Token::ReversedQuote, Token::ReversedQuote,
+            Token::Code("\n> a\n".into()),
+            Token::ReversedQuote, Token::ReversedQuote, Token::ReversedQuote,
+            Token::NewLine, Token::String("b".into()),
+        ]);
+    }
+
+    #[test]
+    fn pipe_tests() {
+        let tokens = |source| -> Vec<Token> { Tokenizer::new(source).tokens().to_vec() };
+
+        // The escaped pipes are text
+        assert_eq!(tokens("| a \\| b |\n|-|"), vec![
+            Token::Pipe, Token::String(" a | b ".into()), Token::Pipe, Token::NewLine,
+            Token::Pipe, Token::String("-".into()), Token::Pipe,
+        ]);
+    }
+}